@@ -1,19 +1,66 @@
 //! Command line argument parsing
 
-use std::collections::HashMap;
+extern crate nix;
+use nix::errno::Errno;
+
 use std::env;
 use std::ffi::CString;
 use std::fmt;
-use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
 
 use crate::err::{Error, Result};
+use crate::overlay::Overlay;
+use crate::policy::{Policy, Rule, Source};
+use crate::resolve;
+use crate::transaction::Resolution;
 use crate::types::{Action, OpenType};
 
+/// What noop was asked to do
+#[derive(Debug)]
+pub enum Command {
+    /// Run a program under a policy
+    Run(Args),
+    /// Apply the staged changes of a kept transactional run
+    Commit(String),
+    /// Roll back the writes of a run with backups
+    Restore(String),
+    /// Replay the opens of a trace file through a policy file
+    Eval(PathBuf, PathBuf),
+    /// Compare the opens of an old and a new trace file, as JSON if set
+    Diff(PathBuf, PathBuf, bool),
+    /// Convert an strace log into a trace printed on stdout
+    Import(PathBuf),
+    /// Write a policy allowing the opens of a trace file to a policy file
+    Generate(PathBuf, PathBuf),
+    /// Show how the policy of some files decides an open of a path in a mode
+    Explain(Vec<PathBuf>, PathBuf, OpenType),
+    /// Check the rules of the policy of some files for mistakes
+    Lint(Vec<PathBuf>),
+    /// Check the decisions of a policy file against a file of expected ones
+    Test(PathBuf, PathBuf),
+}
+
 /// Wrapper for arugments passed to program
+#[derive(Default)]
 pub struct Args {
-    pub paths: HashMap<PathBuf, Action>,
+    pub policy: Policy,
+    /// Overlay receiving writes instead of the real files
+    pub overlay: Option<Overlay>,
+    /// Stage all changes and resolve them as given once the program exits
+    pub transaction: Option<Resolution>,
+    /// Snapshot files before they are first written
+    pub backup: bool,
+    /// Ask on the terminal about opens no rule matches
+    pub ask: bool,
+    /// Only report what the policy would block or redirect
+    pub dry_run: bool,
+    /// Policy file receiving rules from answers that always apply
+    pub learn: Option<PathBuf>,
+    /// Policy file to write from the opens of an unrestricted run
+    pub record: Option<PathBuf>,
+    /// File receiving every open as a line of JSON
+    pub trace: Option<PathBuf>,
     pub show: bool,
     pub argv: Vec<CString>,
 }
@@ -22,13 +69,29 @@ impl fmt::Debug for Args {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "show: {}", self.show)?;
         writeln!(f, "args: {:?}", self.argv)?;
-        writeln!(f, "paths:")?;
-        for (path, action) in &self.paths {
-            write!(f, "\t{:?} => ", path)?;
-            match action {
-                Action::Block(mode) => writeln!(f, "Block {}", mode),
-                Action::Replace(p) => writeln!(f, "{:?}", p),
-            }?;
+        if let Some(overlay) = &self.overlay {
+            writeln!(f, "overlay: {}", overlay.dir.display())?;
+        }
+        if let Some(resolution) = &self.transaction {
+            writeln!(f, "transaction: {:?}", resolution)?;
+        }
+        writeln!(f, "backup: {}", self.backup)?;
+        writeln!(f, "ask: {}", self.ask)?;
+        writeln!(f, "dry run: {}", self.dry_run)?;
+        if let Some(learn) = &self.learn {
+            writeln!(f, "learn into: {}", learn.display())?;
+        }
+        if let Some(record) = &self.record {
+            writeln!(f, "record into: {}", record.display())?;
+        }
+        if let Some(trace) = &self.trace {
+            writeln!(f, "trace into: {}", trace.display())?;
+        }
+        writeln!(f, "default read: {:?}", self.policy.default_read)?;
+        writeln!(f, "default write: {:?}", self.policy.default_write)?;
+        writeln!(f, "rules:")?;
+        for rule in &self.policy.rules {
+            writeln!(f, "\t{} ({})", rule, rule.source)?;
         }
         Ok(())
     }
@@ -39,18 +102,124 @@ static USAGE: &'static str = "\
 noop blocks or modifies calls to open made by the passed program.
 
 USAGE:
-  noop [-lh] [FILE[:rw] | FILE=REPLACE]... -- PROGRAM [ARG]...
+  noop [-lh] [--ask | --dry-run] [--deny-by-default[=rw]] [--overlay DIR] [--transaction[=ask] | --backup] [--trace FILE] [-f POLICY]... [FILE[:rw][:allow|:discard|:ERRNO] | FILE[:rw]=REPLACE | FILE[:r]=@KIND:ARG]... -- PROGRAM [ARG]...
+  noop commit RUN_ID
+  noop restore RUN_ID
+  noop record -o POLICY [-l] -- PROGRAM [ARG]...
+  noop record -o POLICY --from TRACE
+  noop import STRACE_LOG
+  noop explain [-f POLICY]... [--mode r|w|rw] PATH
+  noop lint POLICY...
+  noop test POLICY CASES
+  noop eval POLICY TRACE
+  noop diff [--json] OLD_TRACE NEW_TRACE
 
 FLAGS:
   -l Logs open calls and resulting action to stderr
   -h Show this message and exit
+  -f Read rules from POLICY file, in order with the other rules
+  --ask
+     Pause opens no rule matches and ask on the terminal whether to allow,
+     deny or redirect them, once or always. Answers for always are added
+     to the first POLICY file, which has to be given
+  --dry-run
+     Let every open through unchanged, logging those the rules would
+     block or redirect as WOULD BLOCK or WOULD REDIRECT, and list them
+     by rule on exit
+  --deny-by-default[=rw]
+     Block opens no rule matches, only for reads or writes if =r or =w
+     is given, so only paths with :allow or REPLACE rules can be opened
+  --overlay DIR
+     Send allowed writes to copies of the files under DIR, made from the
+     originals on first write, and serve later reads from those copies
+  --transaction[=ask]
+     Stage all writes, truncations, mode changes, deletes, renames and
+     new directories, failing links, device nodes and owner changes,
+     then show the changes with diffs on exit and ask whether to commit
+     them. With =commit, =discard or =keep the answer is given up front,
+     kept changes are applied later by `noop commit RUN_ID`
+  --backup
+     Let writes through but snapshot each file before its first write,
+     `noop restore RUN_ID` rolls all of them back afterwards
+  --trace FILE
+     Save every open to FILE as a line of JSON, which `noop eval POLICY
+     FILE` replays through POLICY later, reporting what it would allow,
+     block or redirect without running PROGRAM again, and `noop diff
+     OLD NEW` lists paths newly read or written, no longer opened or
+     opened by other programs between two traces, as JSON with --json
 
 ARGS:
   FILE          Block PROGRAM from opening FILE
+                A trailing / applies the rule to everything under FILE
+                Globs such as **/*.log or {a,b} match several files
+                re:REGEX matches paths against a regular expression
       [:rw]     If :r or :w is specified only that opening mode is blocked
+      [:allow]  Allow opening instead, overriding broader rules
+      [:discard] Let opens succeed on /dev/null, so writes vanish
+      [:ERRNO]  Fail blocked opens with ERRNO, such as ENOENT, instead of EPERM
   FILE=REPLACE  Replace open calls to FILE with REPLACE
+      [:rw]=    If :r or :w is given only that opening mode is replaced,
+                read-write opens use the :w replacement
+  DIR/=REPLACE/ Map every path under DIR onto the same path under REPLACE
+                REPLACE may contain {pid}, {exe}, {basename}, {run_id} and
+                {relpath}, the path below DIR or a pattern's leading
+                directories. Missing directories are created for writes
+  FILE=@KIND:ARG
+                Serve generated contents to reads of FILE, from @text:TEXT
+                with \\n and \\t escapes, @file:PATH, the output of
+                @cmd:COMMAND or the value of @env:VAR. Read-write
+                opens of FILE fail with EROFS
+  FILE=@TRANSFORM:SOURCE
+                Serve SOURCE transformed to reads of FILE, decompressed
+                by @gunzip:, with ${VAR} filled in by @subst: or
+                decrypted with KEY by @decrypt:KEY:SOURCE, as encrypted
+                by `openssl enc -aes-256-cbc -pbkdf2 -pass file:KEY`
   PROGRAM       PROGRAM to run and intercept on
   ARGS          ARGS to pass to the PROGRAM
+
+PRECEDENCE:
+  When rules overlap the most specific path wins. Deeper paths beat
+  shallower ones, globs and regexes counting only their literal leading
+  directories. At the same depth a file beats a glob or regex, which
+  beats a directory. Ties go to :r or :w rules over rules for both
+  modes, then to the rule given last.
+
+RECORDING:
+  `noop record -o POLICY -- PROGRAM` runs PROGRAM with every open allowed
+  and writes a policy to POLICY that blocks everything else. Paths only
+  read, only written and both get separate sections. Three or more files
+  in a directory collapse into a glob, and a directory holding nothing
+  but three or more of those files or whole subtrees into a whole tree.
+  With --from TRACE the policy is made from a saved trace
+
+  `noop explain -f POLICY PATH` shows PATH resolved as an open would
+  see it, every rule of POLICY matching it with the winner marked by *,
+  and the resulting action, for reads unless --mode is given
+
+  `noop lint POLICY` reports rules that never apply because another one
+  wins all their matches, paths and redirect directories that do not
+  exist, and fails if any of these is an error. Rules set twice and rules
+  only seen to lose on sample paths are warnings
+
+  `noop test POLICY CASES` checks POLICY against CASES, which holds lines
+  like `w ~/.ssh/id_rsa => block` or `r /etc/hosts => redirect /tmp/hosts`.
+  Outcomes are allow, block, an errno, discard or redirect with an optional
+  target. Failing cases are printed with what happened instead, and any
+  failure makes it exit with 1
+
+  `noop import STRACE_LOG` prints the file calls logged by `strace -f -e
+  trace=file` as a trace, for `noop eval`, `noop diff` or --from TRACE.
+  Relative paths are taken from the directory the process changed to, if
+  the log shows it, else from the current directory, unless `strace -y`
+  shows the directory of the descriptor they are relative to. Add `-e
+  trace=file,process` for children to start in the directory of their parent
+
+POLICY FILES:
+  One rule per line, written as on the command line. A # starting a word
+  begins a comment, `include FILE` reads FILE relative to the including
+  file, `default r|w|rw allow|block|ERRNO` sets the action for paths no
+  rule matches, and ~, $VAR and ${VAR} are expanded. Unset XDG base directory
+  variables such as ${XDG_CONFIG_HOME} take their default values.
 ";
 
 /// Print usage message and exit
@@ -59,14 +228,177 @@ pub fn usage(code: i32) -> ! {
     process::exit(code);
 }
 
-/// Parse `env::args` into `Args` struct
-pub fn parse(args: env::Args) -> Result<Args> {
-    let mut paths: HashMap<PathBuf, Action> = HashMap::new();
+/// Parse `env::args` into the `Command` to carry out
+///
+/// Subcommands are recognized in the first argument only, so rules for files
+/// named like one need a leading `./`.
+pub fn parse(args: env::Args) -> Result<Command> {
+    let args: Vec<String> = args.collect();
+    match args.get(1).map(String::as_str) {
+        Some("commit") => Ok(Command::Commit(run_id(&args[2..])?)),
+        Some("restore") => Ok(Command::Restore(run_id(&args[2..])?)),
+        Some("record") => parse_record(args),
+        Some("explain") => parse_explain(&args[2..]),
+        Some("lint") if args.len() > 2 => Ok(Command::Lint(
+            args[2..].iter().map(|file| parse_path(file)).collect(),
+        )),
+        Some("lint") => Err(Error::Arg {
+            reason: "Missing policy file to lint",
+        }),
+        Some("import") => match &args[2..] {
+            [log] => Ok(Command::Import(parse_path(log))),
+            _ => Err(Error::Arg {
+                reason: "Expected a single strace log",
+            }),
+        },
+        Some("test") => match &args[2..] {
+            [policy, cases] => Ok(Command::Test(parse_path(policy), parse_path(cases))),
+            _ => Err(Error::Arg {
+                reason: "Expected a policy file and a cases file",
+            }),
+        },
+        Some("eval") => match &args[2..] {
+            [policy, trace] => Ok(Command::Eval(parse_path(policy), parse_path(trace))),
+            _ => Err(Error::Arg {
+                reason: "Expected a policy file and a trace file",
+            }),
+        },
+        Some("diff") => {
+            let json = args[2..].iter().any(|arg| arg == "--json");
+            match args[2..]
+                .iter()
+                .filter(|arg| *arg != "--json")
+                .collect::<Vec<_>>()
+                .as_slice()
+            {
+                [old, new] => Ok(Command::Diff(parse_path(old), parse_path(new), json)),
+                _ => Err(Error::Arg {
+                    reason: "Expected an old and a new trace file",
+                }),
+            }
+        }
+        _ => Ok(Command::Run(parse_run(args)?)),
+    }
+}
+
+/// Parse the single run id a subcommand takes
+fn run_id(args: &[String]) -> Result<String> {
+    match args {
+        [id] => Ok(id.clone()),
+        _ => Err(Error::Arg {
+            reason: "Expected a single run id",
+        }),
+    }
+}
+
+/// Parse the arguments of `noop explain`
+fn parse_explain(args: &[String]) -> Result<Command> {
+    let mut files = Vec::new();
+    let mut mode = OpenType::Read;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "-f" => {
+                let file = args.next().ok_or(Error::Arg {
+                    reason: "Missing policy file after -f",
+                })?;
+                files.push(parse_path(file));
+            }
+            "--mode" => {
+                let token = args.next().map_or("", String::as_str);
+                mode = OpenType::parse(token).ok_or(Error::Arg {
+                    reason: "Expected r, w or rw for --mode",
+                })?;
+            }
+            _ if path.is_none() => path = Some(parse_path(arg)),
+            _ => {
+                return Err(Error::Arg {
+                    reason: "Expected a single path to explain",
+                })
+            }
+        }
+    }
+
+    let path = path.ok_or(Error::Arg {
+        reason: "Missing path to explain",
+    })?;
+    Ok(Command::Explain(files, path, mode))
+}
+
+/// Parse the arguments of `noop record`
+///
+/// Recording a program runs it with `Args` allowing everything, recording a
+/// trace only reads the trace.
+fn parse_record(args: Vec<String>) -> Result<Command> {
+    let mut record = None;
+    let mut from = None;
+    let mut show = false;
+    let mut args = args.into_iter().skip(2);
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "-o" => {
+                let file = args.next().ok_or(Error::Arg {
+                    reason: "Missing policy file after -o",
+                })?;
+                record = Some(parse_path(&file));
+            }
+            "--from" => {
+                let file = args.next().ok_or(Error::Arg {
+                    reason: "Missing trace file after --from",
+                })?;
+                from = Some(parse_path(&file));
+            }
+            "-l" => show = true,
+            "--" => break,
+            _ => {
+                return Err(Error::Arg {
+                    reason: "Expected -o POLICY, --from TRACE, -l or -- before the program",
+                })
+            }
+        }
+    }
+
+    let argv = args
+        .map(CString::new)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let record = record.ok_or(Error::Arg {
+        reason: "Missing -o POLICY to record into",
+    })?;
+
+    match (from, argv.is_empty()) {
+        (Some(from), true) => Ok(Command::Generate(from, record)),
+        (Some(_), false) => Err(Error::Arg {
+            reason: "Expected either --from TRACE or a program to record",
+        }),
+        (None, true) => Err(Error::Arg {
+            reason: "No program to execute given",
+        }),
+        (None, false) => Ok(Command::Run(Args {
+            record: Some(record),
+            show,
+            argv,
+            ..Args::default()
+        })),
+    }
+}
+
+/// Parse the arguments of a traced run into `Args` struct
+fn parse_run(args: Vec<String>) -> Result<Args> {
+    let mut policy = Policy::default();
 
     let mut done_flags = false;
+    let mut overlay = None;
+    let mut transaction = None;
+    let mut backup = false;
+    let mut ask = false;
+    let mut dry_run = false;
+    let mut learn = None;
+    let mut trace = None;
     let mut show = false;
     let mut argv = Vec::new();
-    for arg in args.skip(1) {
+    let mut args = args.into_iter().enumerate().skip(1);
+    while let Some((i, arg)) = args.next() {
         if done_flags {
             let cstr = CString::new(arg)?;
             argv.push(cstr);
@@ -76,35 +408,51 @@ pub fn parse(args: env::Args) -> Result<Args> {
         match arg.as_ref() {
             "-l" => show = true,
             "-h" => usage(0),
+            "-f" => {
+                let (_, file) = args.next().ok_or(Error::Arg {
+                    reason: "Missing policy file after -f",
+                })?;
+                policy.include(Path::new(&file))?;
+                learn = learn.or_else(|| Some(parse_path(&file)));
+            }
+            "--overlay" => {
+                let (_, dir) = args.next().ok_or(Error::Arg {
+                    reason: "Missing directory after --overlay",
+                })?;
+                overlay = Some(Overlay::new(parse_path(&dir)));
+            }
+            "--trace" => {
+                let (_, file) = args.next().ok_or(Error::Arg {
+                    reason: "Missing file after --trace",
+                })?;
+                trace = Some(parse_path(&file));
+            }
+            "--backup" => backup = true,
+            "--ask" => ask = true,
+            "--dry-run" => dry_run = true,
+            "--transaction" => transaction = Some(Resolution::Ask),
+            flag if flag.starts_with("--transaction=") => {
+                let resolution = &flag["--transaction=".len()..];
+                transaction = Some(Resolution::parse(resolution).ok_or(Error::Arg {
+                    reason: "Expected ask, commit, discard or keep for --transaction",
+                })?);
+            }
+            "--deny-by-default" => policy.set_default(&OpenType::All, Action::Block(Errno::EPERM)),
+            flag if flag.starts_with("--deny-by-default=") => {
+                let mode = &flag["--deny-by-default=".len()..];
+                let mode = OpenType::parse(mode).ok_or(Error::Arg {
+                    reason: "Expected r, w or rw for --deny-by-default",
+                })?;
+                policy.set_default(&mode, Action::Block(Errno::EPERM));
+            }
             "--" => done_flags = true,
             _ => {
-                let parts: Vec<&str> = arg.split('=').collect();
-                if parts.is_empty() || parts.len() > 2 {
-                    return Err(Error::Arg {
-                        reason: "Bad number of parts in path \"{}\"",
-                    });
-                }
-
-                let first = parts[0];
-                let (path, action) = if parts.len() == 2 {
-                    // Replace
-                    let replace = PathBuf::from(&parts[1]);
-                    (first, Action::Replace(replace))
-                } else if first.ends_with(":w") {
-                    // No write
-                    let new = first.get(..first.len() - 2).unwrap();
-                    (new, Action::Block(OpenType::Write))
-                } else if first.ends_with(":r") {
-                    // No read
-                    let new = first.get(..first.len() - 2).unwrap();
-                    (new, Action::Block(OpenType::Read))
-                } else {
-                    // No open
-                    (first, Action::Block(OpenType::All))
-                };
-
-                let key = parse_path(&path);
-                paths.insert(key, action);
+                let source = Source::Arg(i);
+                let rule = Rule::parse(&arg, source.clone()).map_err(|err| Error::Policy {
+                    at: source.to_string(),
+                    err: Box::new(err),
+                })?;
+                policy.push(rule);
             }
         }
     }
@@ -113,17 +461,48 @@ pub fn parse(args: env::Args) -> Result<Args> {
         Err(Error::Arg {
             reason: "No program to execute given",
         })
+    } else if overlay.is_some() && transaction.is_some() {
+        Err(Error::Arg {
+            reason: "--overlay and --transaction cannot be combined",
+        })
+    } else if backup && (overlay.is_some() || transaction.is_some()) {
+        Err(Error::Arg {
+            reason: "--backup cannot be combined with --overlay or --transaction",
+        })
+    } else if ask && learn.is_none() {
+        Err(Error::Arg {
+            reason: "--ask needs a POLICY file given with -f to save answers to",
+        })
+    } else if dry_run && (ask || backup || overlay.is_some() || transaction.is_some()) {
+        Err(Error::Arg {
+            reason: "--dry-run cannot be combined with --ask, --overlay, --transaction or --backup",
+        })
     } else {
-        Ok(Args { paths, show, argv })
+        Ok(Args {
+            policy,
+            overlay,
+            transaction,
+            backup,
+            ask,
+            dry_run,
+            learn,
+            record: None,
+            trace,
+            show,
+            argv,
+        })
     }
 }
 
-/// Parse name into canonicalize path if possible
+/// Parse name into canonicalized absolute path
 ///
-/// Returns path unchanged if does not exist
+/// Relative names are taken from the current directory, which the child
+/// inherits. Parts of the path that do not exist are kept unchanged.
 pub fn parse_path(name: &str) -> PathBuf {
-    match fs::canonicalize(name) {
-        Ok(full_path) => full_path,
+    let path = match env::current_dir() {
+        Ok(cwd) => cwd.join(name),
         Err(_) => PathBuf::from(name),
-    }
+    };
+
+    resolve::canonicalize(&path)
 }
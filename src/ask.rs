@@ -0,0 +1,108 @@
+//! Questions asked on the controlling terminal
+
+extern crate nix;
+use nix::errno::Errno;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::args::parse_path;
+use crate::policy::is_special;
+use crate::types::{Action, OpenType};
+
+/// Ask `question` on the terminal and return the trimmed answer
+///
+/// Returns `None` if there is no terminal or it was closed.
+pub fn prompt(question: &str) -> Option<String> {
+    let mut tty = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+    write!(tty, "{}", question).ok()?;
+
+    let mut answer = String::new();
+    match BufReader::new(&tty).read_line(&mut answer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(answer.trim().to_string()),
+    }
+}
+
+/// Decision taken for an `open` no rule covers
+#[derive(Debug, Clone, PartialEq)]
+pub struct Answer {
+    pub action: Action,
+    /// Set if the answer should become a rule
+    pub always: bool,
+}
+
+impl Answer {
+    /// Parse answer typed at the `--ask` prompt
+    ///
+    /// Lower case keys apply once, upper case ones always. Redirects take the
+    /// replacement path after the key, as in `r /tmp/scratch`. Paths saved
+    /// always have to be plain rules.
+    pub fn parse(line: &str) -> Option<Answer> {
+        let key = line.chars().next()?;
+        let rest = line[key.len_utf8()..].trim();
+        let always = key.is_ascii_uppercase();
+        let action = match key.to_ascii_lowercase() {
+            'a' if rest.is_empty() => Action::Allow,
+            'd' if rest.is_empty() => Action::Block(Errno::EPERM),
+            'r' if !rest.is_empty() => Action::Replace(parse_path(rest)),
+            _ => return None,
+        };
+        if let (Action::Replace(new), true) = (&action, always) {
+            if is_special(new) {
+                return None;
+            }
+        }
+
+        Some(Answer { action, always })
+    }
+}
+
+/// Ask what to do about `exe` with pid `pid` opening `path` in `mode`
+///
+/// Asks again until the answer makes sense. Returns `None` if there is no
+/// terminal to ask on.
+pub fn open(pid: i32, exe: &str, path: &Path, mode: &OpenType) -> Option<Answer> {
+    let question = format!(
+        "[{}] {} opens {:?} ({})\n\
+         [a]llow or [d]eny once, [A]llow or [D]eny always, [r]edirect or [R]edirect always to PATH: ",
+        pid, exe, path, mode
+    );
+    loop {
+        if let Some(answer) = Answer::parse(&prompt(&question)?) {
+            return Some(answer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    /// Test parsing answers to the prompt
+    #[test]
+    fn answers() {
+        let answer = |action, always| Some(Answer { action, always });
+        assert_eq!(Answer::parse("a"), answer(Action::Allow, false));
+        assert_eq!(
+            Answer::parse("D"),
+            answer(Action::Block(Errno::EPERM), true)
+        );
+        assert_eq!(
+            Answer::parse("R /tmp/x"),
+            answer(Action::Replace(PathBuf::from("/tmp/x")), true)
+        );
+        assert_eq!(Answer::parse("R /tmp/$x"), None);
+        assert!(Answer::parse("r /tmp/$x").is_some());
+        assert_eq!(Answer::parse("r"), None);
+        assert_eq!(Answer::parse("a b"), None);
+        assert_eq!(Answer::parse(""), None);
+    }
+}
@@ -0,0 +1,210 @@
+//! Snapshots of files taken before a run first writes them, for rollback
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::err::{Error, Result};
+use crate::overlay::Overlay;
+use crate::run;
+
+/// State of a path before the run first wrote it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    /// File existed and was copied into the backup
+    Saved(PathBuf),
+    /// File did not exist yet
+    Created(PathBuf),
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Entry::Saved(path) => write!(f, "saved\t{}", path.display()),
+            Entry::Created(path) => write!(f, "created\t{}", path.display()),
+        }
+    }
+}
+
+impl Entry {
+    /// Parse entry from its manifest line
+    fn parse(line: &str) -> Option<Entry> {
+        let mut parts = line.splitn(2, '\t');
+        match (parts.next(), parts.next()) {
+            (Some("saved"), Some(path)) => Some(Entry::Saved(PathBuf::from(path))),
+            (Some("created"), Some(path)) => Some(Entry::Created(PathBuf::from(path))),
+            _ => None,
+        }
+    }
+}
+
+/// Backup of the files written during a run
+///
+/// Copies live in `files` under the run directory, mirroring the file system,
+/// and every snapshot is appended to `manifest` as soon as it is taken.
+#[derive(Debug)]
+pub struct Backup {
+    /// Run directory holding the backup
+    pub dir: PathBuf,
+    files: Overlay,
+    /// Paths already handled, later writes need no snapshot
+    seen: HashSet<PathBuf>,
+}
+
+impl Backup {
+    /// Create an empty backup for run `id`
+    pub fn create(id: &str) -> Result<Backup> {
+        let backup = Backup::at(run::dir(id)?);
+        fs::create_dir_all(&backup.files.dir)?;
+        Ok(backup)
+    }
+
+    /// Open the backup taken by an earlier run `id`
+    pub fn open(id: &str) -> Result<Backup> {
+        let backup = Backup::at(run::dir(id)?);
+        if id.contains('/') || !backup.manifest().is_file() {
+            return Err(Error::Arg {
+                reason: "No backup for that run id",
+            });
+        }
+        Ok(backup)
+    }
+
+    /// Backup kept in run directory `dir`
+    fn at(dir: PathBuf) -> Backup {
+        Backup {
+            files: Overlay::new(dir.join("backup")),
+            seen: HashSet::new(),
+            dir,
+        }
+    }
+
+    /// File listing the entries, one per line
+    fn manifest(&self) -> PathBuf {
+        self.dir.join("manifest")
+    }
+
+    /// Snapshot `path` before it is first written
+    ///
+    /// Returns the new manifest entry, `None` if `path` was handled before or
+    /// is not a regular file.
+    pub fn save(&mut self, path: &Path) -> io::Result<Option<Entry>> {
+        if !self.seen.insert(path.to_path_buf()) {
+            return Ok(None);
+        }
+
+        let entry = match fs::metadata(path) {
+            Ok(meta) if meta.is_file() => {
+                let copy = self.files.copy_of(path);
+                if let Some(dir) = copy.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::copy(path, copy)?;
+                Entry::Saved(path.to_path_buf())
+            }
+            Ok(_) => return Ok(None),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Entry::Created(path.to_path_buf()),
+            Err(e) => return Err(e),
+        };
+
+        let mut manifest = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.manifest())?;
+        writeln!(manifest, "{}", entry)?;
+
+        Ok(Some(entry))
+    }
+
+    /// Entries of the manifest, in the order they were taken
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let manifest = match fs::read_to_string(self.manifest()) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            manifest => manifest?,
+        };
+        manifest
+            .lines()
+            .map(|line| {
+                Entry::parse(line).ok_or(Error::String {
+                    reason: "Malformed backup manifest",
+                })
+            })
+            .collect()
+    }
+
+    /// Put every file back the way it was before the run
+    ///
+    /// Saved files get their old contents back and created files are removed.
+    /// The backup itself is kept.
+    pub fn restore(&self) -> Result<Vec<Entry>> {
+        let entries = self.entries()?;
+        for entry in entries.iter().rev() {
+            match entry {
+                Entry::Saved(path) => {
+                    if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dir)?;
+                    }
+                    fs::copy(self.files.copy_of(path), path)?;
+                }
+                Entry::Created(path) => match fs::remove_file(path) {
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                    res => res?,
+                },
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Report the backup of run `id`, dropping it if nothing was written
+    pub fn finish(&self, id: &str) -> Result<()> {
+        let entries = self.entries()?;
+        if entries.is_empty() {
+            fs::remove_dir_all(&self.dir)?;
+        } else {
+            eprintln!(
+                "\nBacked up {} files, undo with `noop restore {}`",
+                entries.len(),
+                id
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    /// Test that restoring undoes writes and creations
+    #[test]
+    fn restore() {
+        let tmp = env::temp_dir().join(format!("noop-backup-{}", process::id()));
+        let mut backup = Backup::at(tmp.join("run"));
+        let (old, new) = (tmp.join("old"), tmp.join("new"));
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(&old, "old").unwrap();
+
+        let saved = backup.save(&old).unwrap();
+        let again = backup.save(&old).unwrap();
+        let created = backup.save(&new).unwrap();
+        fs::write(&old, "changed").unwrap();
+        fs::write(&new, "new").unwrap();
+        let entries = backup.restore().unwrap();
+        let restored = (fs::read_to_string(&old), new.exists());
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(saved, Some(Entry::Saved(old.clone())));
+        assert_eq!(again, None);
+        assert_eq!(created, Some(Entry::Created(new.clone())));
+        assert_eq!(entries, vec![Entry::Saved(old), Entry::Created(new)]);
+        assert_eq!(restored.0.unwrap(), "old");
+        assert_eq!(restored.1, false);
+    }
+}
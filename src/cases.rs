@@ -0,0 +1,225 @@
+//! Expected decisions checked against a policy, like unit tests for it
+//!
+//! Each line of a cases file holds an `open` and the outcome it should get,
+//! as in `w ~/.ssh/id_rsa => block` or `r /etc/hosts => redirect /tmp/hosts`.
+
+use std::path::{Path, PathBuf};
+
+use crate::args::parse_path;
+use crate::err::{Error, Result};
+use crate::expand::expand;
+use crate::explain::VARS;
+use crate::policy::Policy;
+use crate::types::{parse_errno, Action, OpenType};
+
+use nix::errno::Errno;
+
+/// Outcome a case expects
+#[derive(Debug, Clone, PartialEq)]
+enum Expect {
+    /// The `open` goes through unchanged
+    Allow,
+    /// The `open` fails, with the given error if any
+    Block(Option<Errno>),
+    Discard,
+    /// The `open` is sent elsewhere, to the given target if any
+    Redirect(Option<String>),
+}
+
+impl Expect {
+    /// Parse outcome from the words after `=>`
+    fn parse(words: &[&str]) -> Option<Expect> {
+        match words {
+            ["allow"] => Some(Expect::Allow),
+            ["block"] => Some(Expect::Block(None)),
+            ["block", errno] | [errno] if parse_errno(errno).is_some() => {
+                Some(Expect::Block(parse_errno(errno)))
+            }
+            ["discard"] => Some(Expect::Discard),
+            ["redirect"] => Some(Expect::Redirect(None)),
+            ["redirect", target] => Some(Expect::Redirect(Some(target.to_string()))),
+            _ => None,
+        }
+    }
+
+    /// Checks if `action` sending the `open` to `target` has this outcome
+    fn matches(&self, action: &Action, target: Option<&Path>) -> bool {
+        let redirect = match (action, target) {
+            (Action::Virtual(content), _) => Some(content.to_string()),
+            (Action::Allow, Some(new)) | (Action::Replace(_), Some(new)) => {
+                Some(new.display().to_string())
+            }
+            _ => None,
+        };
+
+        match (self, action) {
+            (Expect::Allow, _) => action.effect(target).is_none(),
+            (Expect::Block(None), Action::Block(_)) => true,
+            (Expect::Block(Some(want)), Action::Block(errno)) => want == errno,
+            (Expect::Discard, Action::Discard) => true,
+            (Expect::Redirect(None), _) => redirect.is_some(),
+            (Expect::Redirect(want), _) => *want == redirect,
+            _ => false,
+        }
+    }
+}
+
+/// Single `open` with the outcome it should get
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    /// Line of the cases file, counting from 1
+    pub line: usize,
+    /// Case as written
+    pub text: String,
+    mode: OpenType,
+    path: PathBuf,
+    expect: Expect,
+}
+
+impl Case {
+    /// Parse case from a line reading `MODE PATH => OUTCOME`
+    ///
+    /// Outcomes are `allow`, `block`, an errno name, `discard` or `redirect`,
+    /// optionally followed by the target as the policy writes it, with
+    /// placeholders filled in from `VARS`.
+    pub fn parse(line: &str, number: usize) -> Result<Case> {
+        let malformed = Error::Arg {
+            reason: "Expected `r|w|rw PATH => allow|block|ERRNO|discard|redirect [TARGET]`",
+        };
+        let text = line.trim().to_string();
+        let line = expand(&text)?;
+
+        let (open, outcome) = match line.find("=>") {
+            Some(i) => (&line[..i], &line[i + 2..]),
+            None => return Err(malformed),
+        };
+        let (mode, path) = match open.trim().find(char::is_whitespace) {
+            Some(i) => open.trim().split_at(i),
+            None => return Err(malformed),
+        };
+        let outcome: Vec<&str> = outcome.split_whitespace().collect();
+
+        match (OpenType::parse(mode), Expect::parse(&outcome)) {
+            (Some(mode), Some(expect)) => Ok(Case {
+                line: number,
+                text,
+                mode,
+                path: parse_path(path.trim()),
+                expect,
+            }),
+            _ => Err(malformed),
+        }
+    }
+
+    /// Check what `policy` does to the `open`, `None` if it is as expected
+    ///
+    /// Otherwise the actual outcome is returned, along with what decided it.
+    pub fn check(&self, policy: &Policy) -> Option<String> {
+        let decision = policy.lookup(&self.path, &self.mode);
+        let target = decision.target(&self.path, &VARS);
+        if self.expect.matches(decision.action, target.as_deref()) {
+            return None;
+        }
+
+        let effect = decision
+            .action
+            .effect(target.as_deref())
+            .unwrap_or_else(|| String::from("ALLOW"));
+        let by = match decision.rule {
+            Some(rule) => format!("{} ({})", rule.pattern, rule.source),
+            None => String::from("default"),
+        };
+        Some(format!("{} by {}", effect, by))
+    }
+}
+
+/// Parse the cases of file `file` holding `text`
+///
+/// Blank lines and lines starting with # are skipped.
+pub fn parse(text: &str, file: &Path) -> Result<Vec<Case>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+        .map(|(i, line)| {
+            Case::parse(line, i + 1).map_err(|err| Error::Policy {
+                at: format!("{}:{}", file.display(), i + 1),
+                err: Box::new(err),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::policy::{Rule, Source};
+
+    /// Failures of the cases in `text` against `rules`
+    fn failures(rules: &[&str], text: &str) -> Vec<(usize, String)> {
+        let mut policy = Policy::default();
+        for (i, rule) in rules.iter().enumerate() {
+            policy.push(Rule::parse(rule, Source::Arg(i)).unwrap());
+        }
+        parse(text, Path::new("cases"))
+            .unwrap()
+            .iter()
+            .filter_map(|case| case.check(&policy).map(|got| (case.line, got)))
+            .collect()
+    }
+
+    /// Test checking every kind of outcome
+    #[test]
+    fn check() {
+        let rules = [
+            "/noop/a:w",
+            "/noop/b:ENOENT",
+            "/noop/c:r=/noop/{exe}",
+            "/noop/d=@text:hi",
+            "/noop/e:discard",
+        ];
+        let text = "# passing\n\
+                    w /noop/a => block\n\
+                    r /noop/a => allow\n\
+                    r /noop/b => ENOENT\n\
+                    \n\
+                    r /noop/b => block ENOENT\n\
+                    r /noop/c => redirect /noop/PROGRAM\n\
+                    r /noop/d => redirect\n\
+                    w /noop/e => discard\n\
+                    # failing\n\
+                    r /noop/a => block\n\
+                    w /noop/b => block EPERM\n\
+                    r /noop/c => redirect /noop/cat\n\
+                    r /noop/d => allow\n";
+        assert_eq!(
+            failures(&rules, text),
+            vec![
+                (11, String::from("ALLOW by default")),
+                (12, String::from("BLOCK (ENOENT) by /noop/b (argument 1)")),
+                (
+                    13,
+                    String::from("REDIRECT => /noop/PROGRAM by /noop/c (argument 2)")
+                ),
+                (
+                    14,
+                    String::from("REDIRECT => @text:hi by /noop/d (argument 3)")
+                ),
+            ]
+        );
+    }
+
+    /// Test rejecting malformed cases with their location
+    #[test]
+    fn malformed() {
+        for text in &[
+            "r /noop/a",
+            "x /noop/a => allow",
+            "r /noop/a => maybe",
+            "r => allow",
+        ] {
+            let err = parse(&format!("\n{}", text), Path::new("cases")).unwrap_err();
+            assert!(err.to_string().contains("cases:2"), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Generated file contents served in place of real files, including
+//! transformed versions of other files
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::args::parse_path;
+use crate::err::{Error, Result};
+use crate::expand::braced;
+
+/// Where the contents of a virtual file come from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Content {
+    /// Literal text, given with `\n`, `\t` and `\\` escapes
+    Text(String),
+    /// Contents of another file at the time of the first open
+    File(PathBuf),
+    /// Output of a shell command, run on the first open
+    Cmd(String),
+    /// Value of an environment variable of noop
+    Env(String),
+    /// Decompressed contents of a gzip file
+    Gunzip(PathBuf),
+    /// Template with `${VAR}` placeholders filled from the environment
+    Subst(PathBuf),
+    /// File encrypted by `openssl enc -aes-256-cbc -pbkdf2` with a key file
+    Decrypt { key: PathBuf, file: PathBuf },
+}
+
+impl Content {
+    /// Parse content from the `@KIND:ARG` replacement of a rule
+    pub fn parse(spec: &str) -> Result<Content> {
+        let sep = spec.find(':').unwrap_or(spec.len());
+        let arg = spec.get(sep + 1..).unwrap_or("");
+        match &spec[..sep] {
+            "@text" => Ok(Content::Text(unescape(arg))),
+            "@file" => Ok(Content::File(parse_path(arg))),
+            "@cmd" => Ok(Content::Cmd(arg.to_string())),
+            "@env" => Ok(Content::Env(arg.to_string())),
+            "@gunzip" => Ok(Content::Gunzip(parse_path(arg))),
+            "@subst" => Ok(Content::Subst(parse_path(arg))),
+            "@decrypt" => match arg.find(':') {
+                Some(i) => Ok(Content::Decrypt {
+                    key: parse_path(&arg[..i]),
+                    file: parse_path(&arg[i + 1..]),
+                }),
+                None => Err(Error::Arg {
+                    reason: "Expected @decrypt:KEY:FILE",
+                }),
+            },
+            _ => Err(Error::Arg {
+                reason:
+                    "Expected @text:, @file:, @cmd:, @env:, @gunzip:, @subst: or @decrypt: contents",
+            }),
+        }
+    }
+
+    /// Produce the contents
+    pub fn generate(&self) -> Result<Vec<u8>> {
+        match self {
+            Content::Text(text) => Ok(text.clone().into_bytes()),
+            Content::File(path) => Ok(fs::read(path)?),
+            Content::Cmd(cmd) => output(Command::new("sh").arg("-c").arg(cmd)),
+            Content::Env(var) => env::var_os(var)
+                .map(OsStringExt::into_vec)
+                .ok_or(Error::Arg {
+                    reason: "Content variable is not set",
+                }),
+            Content::Gunzip(path) => output(Command::new("gzip").arg("-dc").arg(path)),
+            Content::Subst(path) => Ok(braced(&fs::read_to_string(path)?)?.into_bytes()),
+            Content::Decrypt { key, file } => output(
+                Command::new("openssl")
+                    .args("enc -d -aes-256-cbc -pbkdf2 -pass".split(' '))
+                    .arg(format!("file:{}", key.display()))
+                    .arg("-in")
+                    .arg(file),
+            ),
+        }
+    }
+}
+
+/// Standard output of `cmd`, which has to succeed
+fn output(cmd: &mut Command) -> Result<Vec<u8>> {
+    let out = cmd.output()?;
+    if out.status.success() {
+        Ok(out.stdout)
+    } else {
+        Err(Error::Arg {
+            reason: "Content command failed",
+        })
+    }
+}
+
+impl fmt::Display for Content {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Content::Text(text) => write!(f, "@text:{}", escape(text)),
+            Content::File(path) => write!(f, "@file:{}", path.display()),
+            Content::Cmd(cmd) => write!(f, "@cmd:{}", cmd),
+            Content::Env(var) => write!(f, "@env:{}", var),
+            Content::Gunzip(path) => write!(f, "@gunzip:{}", path.display()),
+            Content::Subst(path) => write!(f, "@subst:{}", path.display()),
+            Content::Decrypt { key, file } => {
+                write!(f, "@decrypt:{}:{}", key.display(), file.display())
+            }
+        }
+    }
+}
+
+/// Replace `\n`, `\t` and `\\` escapes in `text`
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Inverse of `unescape`
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+}
+
+/// Create a directory only the user can enter, named `prefix` followed by
+/// random characters, in the temporary directory
+///
+/// Like `mkdtemp` this fails instead of reusing an existing path, so other
+/// users cannot prepare the directory or plant a symlink in its place.
+fn private_dir(prefix: &str) -> Result<PathBuf> {
+    let mut random = [0u8; 8];
+    fs::File::open("/dev/urandom")?.read_exact(&mut random)?;
+    let suffix: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let dir = env::temp_dir().join(format!("{}{}", prefix, suffix));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    Ok(dir)
+}
+
+/// Private directory of files holding the contents generated during a run
+///
+/// Each distinct content is generated once, on the first open, and served
+/// from the same read-only file afterwards. The directory is removed on drop.
+pub struct Store {
+    prefix: String,
+    /// Directory, once created
+    dir: Option<PathBuf>,
+    files: HashMap<String, PathBuf>,
+}
+
+impl Store {
+    /// Store for run `id`, created on first use
+    pub fn new(id: &str) -> Store {
+        Store {
+            prefix: format!("noop-{}-", id),
+            dir: None,
+            files: HashMap::new(),
+        }
+    }
+
+    /// File holding `content`, generating it if needed
+    pub fn file(&mut self, content: &Content) -> Result<&Path> {
+        let key = content.to_string();
+        if !self.files.contains_key(&key) {
+            if self.dir.is_none() {
+                self.dir = Some(private_dir(&self.prefix)?);
+            }
+            let dir = self.dir.as_ref().unwrap();
+            let file = dir.join(self.files.len().to_string());
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o400)
+                .open(&file)?
+                .write_all(&content.generate()?)?;
+            self.files.insert(key.clone(), file);
+        }
+
+        Ok(&self.files[&key])
+    }
+}
+
+impl Drop for Store {
+    /// Remove generated files on drop
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Test parsing and showing content sources
+    #[test]
+    fn parse() {
+        let text = Content::parse("@text:a=b\\n").unwrap();
+        assert_eq!(text, Content::Text(String::from("a=b\n")));
+        assert_eq!(text.to_string(), "@text:a=b\\n");
+        assert_eq!(
+            Content::parse("@cmd:echo hi").unwrap(),
+            Content::Cmd(String::from("echo hi"))
+        );
+        assert!(Content::parse("@nope:x").is_err());
+    }
+
+    /// Test generating contents
+    #[test]
+    fn generate() {
+        let cmd = Content::Cmd(String::from("echo hi"));
+        assert_eq!(cmd.generate().unwrap(), b"hi\n");
+        assert!(Content::Cmd(String::from("false")).generate().is_err());
+        assert!(Content::Env(String::from("NOOP_UNSET_VARIABLE"))
+            .generate()
+            .is_err());
+    }
+
+    /// Test that stores get fresh directories and remove only those
+    #[test]
+    fn store() {
+        let taken = env::temp_dir().join(format!("noop-store-{}", std::process::id()));
+        fs::create_dir_all(&taken).unwrap();
+        let mut store = Store::new(&format!("store-{}", std::process::id()));
+        let text = Content::Text(String::from("hi"));
+        let file = store.file(&text).unwrap().to_path_buf();
+        let dir = file.parent().unwrap().to_path_buf();
+
+        assert_ne!(dir, taken);
+        assert_eq!(fs::read(&file).unwrap(), b"hi");
+        let mode = fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o400);
+        assert_eq!(store.file(&text).unwrap(), file.as_path());
+        drop(store);
+        assert!(!dir.exists());
+        assert!(taken.is_dir());
+        let _ = fs::remove_dir(&taken);
+    }
+}
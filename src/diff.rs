@@ -0,0 +1,233 @@
+//! Comparison of the opens saved in two traces
+
+use serde_json::{json, Value};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::err::Result;
+use crate::trace;
+use crate::trace::Access;
+use crate::types::OpenType;
+
+/// How a trace used a single path
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Usage {
+    read: bool,
+    written: bool,
+    /// Programs that opened the path
+    exes: BTreeSet<String>,
+}
+
+/// Usage of every path opened in `accesses`
+fn usage(accesses: &[Access]) -> BTreeMap<&Path, Usage> {
+    let mut paths: BTreeMap<&Path, Usage> = BTreeMap::new();
+    for access in accesses {
+        let usage = paths.entry(&access.path).or_default();
+        usage.read |= access.mode != OpenType::Write;
+        usage.written |= access.mode != OpenType::Read;
+        usage.exes.insert(access.exe.clone());
+    }
+    paths
+}
+
+/// Path along with the programs that opened it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opened {
+    pub path: PathBuf,
+    pub exes: Vec<String>,
+}
+
+/// Path opened by different programs in the two traces
+#[derive(Debug, Clone, PartialEq)]
+pub struct Moved {
+    pub path: PathBuf,
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+}
+
+/// Differences between an old and a new trace
+#[derive(Debug, Default, PartialEq)]
+pub struct Diff {
+    /// Paths read by the new trace but not the old one
+    pub read: Vec<Opened>,
+    /// Paths written by the new trace but not the old one
+    pub written: Vec<Opened>,
+    /// Paths only the old trace opened, with the programs that did
+    pub gone: Vec<Opened>,
+    /// Paths both opened, but from different programs
+    pub moved: Vec<Moved>,
+}
+
+impl Diff {
+    /// Compare the accesses of trace `old` with those of `new`
+    pub fn new(old: &[Access], new: &[Access]) -> Diff {
+        let (old, new) = (usage(old), usage(new));
+        let opened = |path: &Path, usage: &Usage| Opened {
+            path: path.to_path_buf(),
+            exes: usage.exes.iter().cloned().collect(),
+        };
+
+        let mut diff = Diff::default();
+        for (path, now) in &new {
+            let before = old.get(path).cloned().unwrap_or_default();
+            if now.read && !before.read {
+                diff.read.push(opened(path, now));
+            }
+            if now.written && !before.written {
+                diff.written.push(opened(path, now));
+            }
+            if old.contains_key(path) && now.exes != before.exes {
+                diff.moved.push(Moved {
+                    path: path.to_path_buf(),
+                    old: before.exes.into_iter().collect(),
+                    new: now.exes.iter().cloned().collect(),
+                });
+            }
+        }
+        for (path, before) in &old {
+            if !new.contains_key(path) {
+                diff.gone.push(opened(path, before));
+            }
+        }
+
+        diff
+    }
+
+    /// Checks if the traces opened the same paths the same ways
+    pub fn is_empty(&self) -> bool {
+        self.read.is_empty()
+            && self.written.is_empty()
+            && self.gone.is_empty()
+            && self.moved.is_empty()
+    }
+
+    /// Differences as a JSON object
+    pub fn to_json(&self) -> Value {
+        let opened = |list: &[Opened]| -> Vec<Value> {
+            list.iter()
+                .map(|o| json!({ "path": o.path.to_string_lossy(), "exes": o.exes }))
+                .collect()
+        };
+        let moved: Vec<Value> = self
+            .moved
+            .iter()
+            .map(|m| json!({ "path": m.path.to_string_lossy(), "old": m.old, "new": m.new }))
+            .collect();
+
+        json!({
+            "newly_read": opened(&self.read),
+            "newly_written": opened(&self.written),
+            "no_longer_accessed": opened(&self.gone),
+            "process_changes": moved,
+        })
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No differences");
+        }
+
+        let sections = [
+            ("NEWLY READ", &self.read),
+            ("NEWLY WRITTEN", &self.written),
+            ("NO LONGER ACCESSED", &self.gone),
+        ];
+        for (title, list) in sections.iter().filter(|(_, list)| !list.is_empty()) {
+            writeln!(f, "{}:", title)?;
+            for opened in list.iter() {
+                writeln!(
+                    f,
+                    "  {} (by {})",
+                    opened.path.display(),
+                    opened.exes.join(", ")
+                )?;
+            }
+        }
+        if !self.moved.is_empty() {
+            writeln!(f, "PROCESS CHANGES:")?;
+            for moved in &self.moved {
+                writeln!(
+                    f,
+                    "  {}: {} -> {}",
+                    moved.path.display(),
+                    moved.old.join(", "),
+                    moved.new.join(", ")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Print the differences between traces `old` and `new`, as JSON if `json`
+pub fn diff(old: &Path, new: &Path, json: bool) -> Result<()> {
+    let old = trace::parse(&fs::read_to_string(old)?)?;
+    let new = trace::parse(&fs::read_to_string(new)?)?;
+    let diff = Diff::new(&old, &new);
+
+    if json {
+        println!("{}", diff.to_json());
+    } else {
+        print!("{}", diff);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Build an access of `path` by `exe`
+    fn access(exe: &str, path: &str, mode: OpenType) -> Access {
+        Access {
+            pid: 1,
+            exe: String::from(exe),
+            syscall: String::from("openat"),
+            path: PathBuf::from(path),
+            mode,
+            flags: None,
+            result: None,
+        }
+    }
+
+    /// Test finding new, dropped and moved paths
+    #[test]
+    fn compare() {
+        let old = vec![
+            access("cat", "/noop/same", OpenType::Read),
+            access("cat", "/noop/gone", OpenType::Read),
+            access("cat", "/noop/now-written", OpenType::Read),
+            access("cat", "/noop/moved", OpenType::Read),
+        ];
+        let new = vec![
+            access("cat", "/noop/same", OpenType::Read),
+            access("cat", "/noop/now-written", OpenType::All),
+            access("sh", "/noop/moved", OpenType::Read),
+            access("sh", "/noop/new", OpenType::Read),
+        ];
+        let opened = |exe: &str, path: &str| Opened {
+            path: PathBuf::from(path),
+            exes: vec![String::from(exe)],
+        };
+
+        let diff = Diff::new(&old, &new);
+        assert_eq!(diff.read, vec![opened("sh", "/noop/new")]);
+        assert_eq!(diff.written, vec![opened("cat", "/noop/now-written")]);
+        assert_eq!(diff.gone, vec![opened("cat", "/noop/gone")]);
+        assert_eq!(
+            diff.moved,
+            vec![Moved {
+                path: PathBuf::from("/noop/moved"),
+                old: vec![String::from("cat")],
+                new: vec![String::from("sh")],
+            }]
+        );
+        assert_eq!(diff.to_json()["newly_read"][0]["path"], "/noop/new");
+        assert!(Diff::new(&old, &old).is_empty());
+    }
+}
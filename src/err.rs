@@ -18,6 +18,11 @@ pub enum Error {
     String { reason: &'static str },
     /// Errors from seccomp
     Seccomp { src: &'static str },
+    /// Errors located in a policy file
+    Policy {
+        at: std::string::String,
+        err: Box<Error>,
+    },
 }
 
 /// Crate `Result` type
@@ -32,6 +37,7 @@ impl fmt::Display for Error {
             OS { err } => write!(f, "OS: {}", err),
             String { reason } => write!(f, "String: {}", reason),
             Seccomp { src } => write!(f, "Seccomp: {}", src),
+            Policy { at, err } => write!(f, "{}: {}", at, err),
         }
     }
 }
@@ -54,6 +60,12 @@ impl From<NoneError> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::OS { err: err.into() }
+    }
+}
+
 impl From<nix::Error> for Error {
     fn from(err: nix::Error) -> Self {
         Error::OS { err: err.into() }
@@ -65,3 +77,15 @@ impl From<std::str::Utf8Error> for Error {
         Error::Parse { err: err.into() }
     }
 }
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Parse { err: err.into() }
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(err: regex::Error) -> Self {
+        Error::Parse { err: err.into() }
+    }
+}
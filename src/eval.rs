@@ -0,0 +1,125 @@
+//! Offline evaluation of a policy against the opens of a saved trace
+
+use std::fs;
+use std::path::Path;
+
+use crate::err::Result;
+use crate::policy::{Policy, Vars};
+use crate::run;
+use crate::trace;
+use crate::trace::Access;
+
+/// Number of replayed opens per outcome
+#[derive(Debug, Default, PartialEq)]
+pub struct Tally {
+    pub allowed: usize,
+    pub blocked: usize,
+    pub redirected: usize,
+}
+
+/// Replay `accesses` through `policy` as the run `id` would see them
+///
+/// Returns a line describing the outcome of each access along with the tally.
+pub fn replay(policy: &Policy, accesses: &[Access], id: &str) -> (Vec<String>, Tally) {
+    let mut tally = Tally::default();
+    let lines = accesses
+        .iter()
+        .map(|access| {
+            let decision = policy.lookup(&access.path, &access.mode);
+            let vars = Vars {
+                pid: access.pid,
+                exe: &access.exe,
+                run_id: id,
+            };
+            let target = decision.target(&access.path, &vars);
+
+            let effect = match decision.action.effect(target.as_deref()) {
+                Some(effect) => {
+                    if decision.action.allows() {
+                        tally.redirected += 1;
+                    } else {
+                        tally.blocked += 1;
+                    }
+                    effect
+                }
+                None => {
+                    tally.allowed += 1;
+                    String::from("ALLOW")
+                }
+            };
+            let mut line = format!(
+                "[{}] {} {}({:?}, {}) {}",
+                access.pid, access.exe, access.syscall, access.path, access.mode, effect
+            );
+            if let Some(by) = decision.by() {
+                line.push_str(&format!(" by {}", by));
+            }
+            line
+        })
+        .collect();
+
+    (lines, tally)
+}
+
+/// Print what policy file `policy` makes of each open saved in `trace`
+pub fn eval(policy: &Path, trace: &Path) -> Result<()> {
+    let mut rules = Policy::default();
+    rules.include(policy)?;
+    let accesses = trace::parse(&fs::read_to_string(trace)?)?;
+
+    let (lines, tally) = replay(&rules, &accesses, &run::id());
+    for line in lines {
+        println!("{}", line);
+    }
+    println!(
+        "\n{} allowed, {} blocked, {} redirected",
+        tally.allowed, tally.blocked, tally.redirected
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::policy::{Rule, Source};
+    use crate::types::OpenType;
+
+    /// Test replaying opens through rules
+    #[test]
+    fn replay() {
+        let mut policy = Policy::default();
+        for (i, rule) in ["/noop/a", "/noop/b:r=/noop/{exe}"].iter().enumerate() {
+            policy.push(Rule::parse(rule, Source::Arg(i)).unwrap());
+        }
+        let access = |path: &str, mode| Access {
+            pid: 7,
+            exe: String::from("cat"),
+            syscall: String::from("openat"),
+            path: PathBuf::from(path),
+            mode,
+            flags: None,
+            result: None,
+        };
+        let accesses = vec![
+            access("/noop/a", OpenType::Read),
+            access("/noop/b", OpenType::Read),
+            access("/noop/b", OpenType::Write),
+        ];
+
+        let (lines, tally) = super::replay(&policy, &accesses, "run");
+        assert_eq!(
+            tally,
+            Tally {
+                allowed: 1,
+                blocked: 1,
+                redirected: 1,
+            }
+        );
+        assert!(lines[0].ends_with("BLOCK (EPERM) by /noop/a (argument 0)"));
+        assert!(lines[1].ends_with("REDIRECT => /noop/cat by /noop/b (argument 1)"));
+        assert!(lines[2].ends_with("W) ALLOW"));
+    }
+}
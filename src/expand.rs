@@ -0,0 +1,141 @@
+//! Expansion of `~` and environment variables in policy files and templates
+
+use std::env;
+
+use crate::err::{Error, Result};
+
+/// Expand `~`, `$VAR` and `${VAR}` in `s`
+///
+/// `~` is only expanded at the start of a word or after `=`. A `$` not
+/// followed by a variable name is kept, so regex anchors survive.
+pub fn expand(s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut skip = 0;
+    for (i, c) in s.char_indices() {
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+
+        let rest = &s[i + 1..];
+        match c {
+            '~' if starts_word(&s[..i]) && (rest.is_empty() || rest.starts_with('/')) => {
+                out.push_str(&var("HOME")?);
+            }
+            '$' => {
+                let (name, len) = if rest.starts_with('{') {
+                    match rest.find('}') {
+                        Some(end) => (&rest[1..end], end + 1),
+                        None => {
+                            return Err(Error::Arg {
+                                reason: "Unterminated ${ in policy",
+                            })
+                        }
+                    }
+                } else {
+                    let end = rest
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(rest.len());
+                    (&rest[..end], end)
+                };
+
+                if name.is_empty() {
+                    out.push('$');
+                } else {
+                    out.push_str(&var(name)?);
+                    // Names and braces are ASCII, so bytes are chars
+                    skip = len;
+                }
+            }
+            c => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Expand only `${VAR}` in `s`, leaving `~` and `$VAR` alone
+///
+/// Used for configuration templates, where a bare `$` is often meant
+/// literally.
+pub fn braced(s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..].find('}').ok_or(Error::Arg {
+            reason: "Unterminated ${ in template",
+        })? + start;
+        out.push_str(&rest[..start]);
+        out.push_str(&var(&rest[start + 2..end])?);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// Checks if a `~` following `before` starts a word
+fn starts_word(before: &str) -> bool {
+    match before.chars().last() {
+        None | Some('=') => true,
+        Some(c) => c.is_whitespace(),
+    }
+}
+
+/// Look up environment variable `name`
+///
+/// Unset XDG base directories fall back to their specified defaults.
+fn var(name: &str) -> Result<String> {
+    if let Ok(value) = env::var(name) {
+        return Ok(value);
+    }
+
+    let home = || {
+        env::var("HOME").map_err(|_| Error::Arg {
+            reason: "HOME is not set",
+        })
+    };
+    match name {
+        "XDG_CONFIG_HOME" => Ok(home()? + "/.config"),
+        "XDG_CACHE_HOME" => Ok(home()? + "/.cache"),
+        "XDG_DATA_HOME" => Ok(home()? + "/.local/share"),
+        "XDG_STATE_HOME" => Ok(home()? + "/.local/state"),
+        "XDG_RUNTIME_DIR" => Ok(format!("/run/user/{}", nix::unistd::getuid())),
+        _ => Err(Error::Arg {
+            reason: "Unset variable in policy",
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test variable and home expansion
+    #[test]
+    fn vars() {
+        env::set_var("NOOP_EXPAND", "/x");
+        let home = env::var("HOME").unwrap();
+        assert_eq!(expand("$NOOP_EXPAND/a").unwrap(), "/x/a");
+        assert_eq!(expand("${NOOP_EXPAND}a").unwrap(), "/xa");
+        assert_eq!(expand("~/a=~/b").unwrap(), format!("{}/a={}/b", home, home));
+        assert_eq!(expand("a~/b").unwrap(), "a~/b");
+        assert!(expand("$NOOP_UNSET_VARIABLE").is_err());
+    }
+
+    /// Test that templates only expand braced variables
+    #[test]
+    fn templates() {
+        env::set_var("NOOP_BRACED", "x");
+        assert_eq!(braced("~/$HOME/${NOOP_BRACED}y").unwrap(), "~/$HOME/xy");
+        assert!(braced("${NOOP_BRACED").is_err());
+        assert!(braced("${NOOP_UNSET_VARIABLE}").is_err());
+    }
+
+    /// Test that regex anchors are kept
+    #[test]
+    fn dollar() {
+        assert_eq!(expand("re:^/a$").unwrap(), "re:^/a$");
+        assert_eq!(expand("re:^/a$|^/b").unwrap(), "re:^/a$|^/b");
+    }
+}
@@ -0,0 +1,81 @@
+//! Explanation of how a policy decides a single `open`
+
+use std::path::Path;
+use std::ptr;
+
+use crate::policy::{Policy, Vars};
+use crate::types::OpenType;
+
+/// Values filled into replacement placeholders when no program runs
+pub const VARS: Vars<'static> = Vars {
+    pid: 0,
+    exe: "PROGRAM",
+    run_id: "RUN_ID",
+};
+
+/// Describe the rules `policy` weighs for an `open` of `path` in `mode`
+///
+/// Every matching rule is listed, the preferred first, with the winner marked
+/// by a `*`. Replacement placeholders are filled in from `VARS`.
+pub fn explain(policy: &Policy, path: &Path, mode: &OpenType) -> String {
+    let decision = policy.lookup(path, mode);
+    let target = decision.target(path, &VARS);
+
+    let mut out = format!("path:   {}\nmode:   {}\nrules:\n", path.display(), mode);
+    let candidates = policy.candidates(path, mode);
+    if candidates.is_empty() {
+        out.push_str("    none\n");
+    }
+    for rule in candidates {
+        let mark = match decision.rule {
+            Some(winner) if ptr::eq(winner, rule) => '*',
+            _ => ' ',
+        };
+        out.push_str(&format!("  {} {} ({})\n", mark, rule, rule.source));
+    }
+
+    let effect = decision
+        .action
+        .effect(target.as_deref())
+        .unwrap_or_else(|| String::from("ALLOW"));
+    let by = match decision.rule {
+        Some(rule) => format!("{} ({})", rule.pattern, rule.source),
+        None => String::from("default"),
+    };
+    out.push_str(&format!("action: {} by {}\n", effect, by));
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::policy::{Rule, Source};
+
+    /// Test listing candidates and marking the winner
+    #[test]
+    fn explain() {
+        let mut policy = Policy::default();
+        for (i, rule) in ["/noop/", "/noop/a:w=/tmp/{exe}", "/other"]
+            .iter()
+            .enumerate()
+        {
+            policy.push(Rule::parse(rule, Source::Arg(i)).unwrap());
+        }
+
+        let text = super::explain(&policy, Path::new("/noop/a"), &OpenType::Write);
+        assert_eq!(
+            text,
+            "path:   /noop/a\n\
+             mode:   W\n\
+             rules:\n  \
+             * /noop/a:w=/tmp/{exe} (argument 1)\n    \
+             /noop/ (argument 0)\n\
+             action: REDIRECT => /tmp/PROGRAM by /noop/a (argument 1)\n"
+        );
+
+        let text = super::explain(&policy, Path::new("/else"), &OpenType::Read);
+        assert!(text.contains("rules:\n    none\n"));
+        assert!(text.ends_with("action: ALLOW by default\n"));
+    }
+}
@@ -1,48 +1,70 @@
 //! Code for intercepting and handling child process syscalls
 
 extern crate nix;
+use nix::errno::Errno;
 use nix::libc::user_regs_struct as Regs;
+use nix::libc::{AT_REMOVEDIR, EIO, EPERM, EXDEV, O_CREAT, O_TRUNC, RENAME_NOREPLACE};
 use nix::sys::ptrace;
-use nix::sys::ptrace::Options;
+use nix::sys::ptrace::{Event, Options};
 use nix::sys::signal::{kill, Signal};
-use nix::sys::wait::waitpid;
+use nix::sys::wait::{waitpid, WaitPidFlag};
 use nix::unistd::{execvp, fork, getpid, ForkResult, Pid};
 
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::CString;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process;
 
 use crate::args::Args;
-use crate::err::Result;
+use crate::ask;
+use crate::ask::Answer;
+use crate::backup::Backup;
+use crate::content::Store;
+use crate::err::{Error, Result};
+use crate::policy::{Pattern, Policy, Rule, Source, Vars};
+use crate::record;
+use crate::resolve;
+use crate::run;
+use crate::trace::Access;
+use crate::transaction::Transaction;
 use crate::types::{Action, OpenType};
 
 mod child;
 mod syscall;
-use self::syscall::Syscall;
+pub use self::syscall::Syscall;
 mod seccomp;
 use self::seccomp::Context;
 
 /// Parse child address holding a `CString` into a `PathBuf`
 ///
+/// The path is resolved relative to `dirfd` in the child, see
+/// `resolve::in_tracee`. A final symlink is only followed if `follow` is set.
+///
 /// This function is marked unsafe as `addr` must be the address of a `CString`
 /// or behavior is undefined.
-unsafe fn user_path(pid: Pid, addr: u64) -> Result<PathBuf> {
+unsafe fn user_path(pid: Pid, dirfd: Option<i32>, addr: u64, follow: bool) -> Result<PathBuf> {
     let path = child::read_data(pid, addr, None)?;
     let path = std::str::from_utf8(&path)?;
-    let path = match fs::canonicalize(path) {
-        Ok(pathbuf) => pathbuf,
-        Err(_) => PathBuf::from(path),
-    };
 
-    Ok(path)
+    if follow {
+        Ok(resolve::in_tracee(pid, dirfd, path))
+    } else {
+        Ok(resolve::entry_in_tracee(pid, dirfd, path))
+    }
+}
+
+/// Return value of a call failed with the OS error in `err`
+fn failure(err: &std::io::Error) -> i64 {
+    -i64::from(err.raw_os_error().unwrap_or(EIO))
 }
 
 /// Rewrite `arg` to redirect `open` call to `new` path
 ///
 /// This function extends the child process stack, writes the new path,
 /// and updates the path argument in `arg` to point to this new value.
-fn redirect_path(pid: Pid, stack: u64, arg: &mut u64, new: &PathBuf) -> Result<()> {
+fn redirect_path(pid: Pid, stack: u64, arg: &mut u64, new: &Path) -> Result<()> {
     let mut path = CString::new(new.to_str()?.as_bytes())?.into_bytes_with_nul();
 
     // Place string below 128B redzone
@@ -56,18 +78,19 @@ fn redirect_path(pid: Pid, stack: u64, arg: &mut u64, new: &PathBuf) -> Result<(
     Ok(())
 }
 
-/// Fork child to run passed program and begin tracing
-fn trace_child(argv: &[CString]) -> Result<Pid> {
+/// Fork child to run passed program and begin tracing `calls`
+fn trace_child(argv: &[CString], calls: &[Syscall]) -> Result<Pid> {
     let pid = match fork()? {
         ForkResult::Parent { child } => child,
         ForkResult::Child => {
             ptrace::traceme()?;
 
             // Create seccomp filter
-            Context::new()?
-                .trace(Syscall::Open as i32)?
-                .trace(Syscall::OpenAt as i32)?
-                .load()?;
+            let mut filter = Context::new()?;
+            for call in calls {
+                filter = filter.trace(*call as i32)?;
+            }
+            filter.load()?;
 
             // Sync with tracer
             kill(getpid(), Signal::SIGSTOP)?;
@@ -88,6 +111,14 @@ fn trace_child(argv: &[CString]) -> Result<Pid> {
     options.insert(Options::PTRACE_O_EXITKILL);
     // Catch seccomp filter
     options.insert(Options::PTRACE_O_TRACESECCOMP);
+    // Follow new processes and threads
+    options.insert(Options::PTRACE_O_TRACEFORK);
+    options.insert(Options::PTRACE_O_TRACEVFORK);
+    options.insert(Options::PTRACE_O_TRACECLONE);
+    // Report exec as an event rather than a stray SIGTRAP
+    options.insert(Options::PTRACE_O_TRACEEXEC);
+    // Tell syscall stops apart from signals
+    options.insert(Options::PTRACE_O_TRACESYSGOOD);
     if ptrace::setoptions(pid, options).is_err() {
         eprintln!("Failed to trace child");
         process::exit(1);
@@ -96,71 +127,556 @@ fn trace_child(argv: &[CString]) -> Result<Pid> {
     Ok(pid)
 }
 
+/// Settings and state of the current run shared by all handlers
+struct Run<'a> {
+    args: &'a Args,
+    /// Policy of the run, extended by answers to `--ask`
+    policy: Policy,
+    id: String,
+    /// Staging area if the run is transactional
+    transaction: Option<Transaction>,
+    /// Snapshots of written files if backups are on
+    backup: Option<Backup>,
+    /// Files holding generated contents
+    contents: Store,
+    /// Opens seen so far if the run is recorded
+    accesses: Vec<Access>,
+    /// Trace file receiving every open
+    trace: Option<fs::File>,
+    /// Opens a dry run let through against the rules, counted by rule
+    violations: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
 /// Handle child call to `open`
-fn handle_open(pid: Pid, args: &Args, regs: &mut Regs) -> Result<()> {
+///
+/// Returns the value to return from the call if it was skipped, which has to
+/// be set once the skipped syscall exits.
+fn handle_open(pid: Pid, run: &mut Run, regs: &mut Regs) -> Result<Option<i64>> {
     let sys = Syscall::from(regs.orig_rax);
+    let args = run.args;
 
     // Read path from child
-    let path = unsafe { user_path(pid, *sys.path(regs))? };
+    let path = unsafe { user_path(pid, sys.dirfd(regs), *sys.path(regs), true)? };
 
     // Parse open mode from flag register
     let mode = OpenType::from(sys.flag(regs));
 
+    let exe = resolve::exe(pid);
+
+    if args.record.is_some() || run.trace.is_some() {
+        let access = Access {
+            pid: pid.as_raw(),
+            exe: exe.clone(),
+            syscall: sys.to_string(),
+            path: path.clone(),
+            mode: mode.clone(),
+            flags: Some(sys.flag(regs) as i32),
+            result: None,
+        };
+        if let Some(trace) = &mut run.trace {
+            writeln!(trace, "{}", access.to_json())?;
+        }
+        if args.record.is_some() {
+            run.accesses.push(access);
+        }
+    }
+
+    // Ask about opens no rule covers, the tracee waits at its seccomp stop
+    let mut answer = None;
+    if args.ask && run.policy.lookup(&path, &mode).rule.is_none() {
+        answer = ask::open(pid.as_raw(), &exe, &path, &mode);
+        if let Some(Answer {
+            action,
+            always: true,
+        }) = &answer
+        {
+            let rule = Rule {
+                pattern: Pattern::Exact(path.clone()),
+                mode: mode.clone(),
+                action: action.clone(),
+                source: Source::Answer,
+                comment: None,
+            };
+            run.policy.learn(rule, args.learn.as_deref())?;
+        }
+    }
+
     // Check if permitted
-    let action = args.paths.get(&path);
-    let allowed = action.as_ref().map_or(true, |a| a.allows(&mode));
+    let decision = run.policy.lookup(&path, &mode);
+    let mut action = decision.action.clone();
+    let vars = Vars {
+        pid: pid.as_raw(),
+        exe: &exe,
+        run_id: &run.id,
+    };
+    let mut target = decision.target(&path, &vars);
+    let mut by = decision.by();
+
+    if let Some(Answer {
+        action: once,
+        always: false,
+    }) = answer
+    {
+        if let Action::Replace(new) = &once {
+            target = Some(new.clone());
+        }
+        action = once;
+        by = Some(String::from("answer"));
+    }
+
+    if args.dry_run {
+        let call = format!("{}({:?}, {})", sys, path, mode);
+        audit(run, pid, &call, &action, &target, by);
+        return Ok(None);
+    }
+
+    if let Action::Virtual(content) = decision.action {
+        // Serve generated contents from a private file
+        match run.contents.file(content) {
+            Ok(file) => target = Some(file.to_path_buf()),
+            Err(e) => {
+                eprintln!("Failed to generate {}: {}", content, e);
+                action = Action::Block(Errno::EIO);
+            }
+        }
+    }
+
+    if let Action::Allow = action {
+        // Creating or truncating changes the file even in a read-only open
+        let changes = sys.flag(regs) & (O_CREAT | O_TRUNC) as u64 != 0;
+        let mode = if changes { OpenType::All } else { mode.clone() };
+
+        // Choose between the original and its copy
+        let (layer, copy) = match (&run.transaction, &args.overlay) {
+            (Some(transaction), _) => ("staging", transaction.target(&path, &mode)),
+            (None, Some(overlay)) => ("overlay", overlay.target(&path, &mode)),
+            (None, None) => ("", Ok(None)),
+        };
+        match copy {
+            Ok(None) => (),
+            Ok(copy) => {
+                target = copy;
+                by = Some(String::from(layer));
+            }
+            Err(e) => {
+                let errno = Errno::from_i32(-failure(&e) as i32);
+                action = Action::Block(errno);
+                by = Some(String::from(layer));
+            }
+        }
+    }
+
+    let mut saved = false;
+    let write = mode != OpenType::Read && action.allows();
+    if let (Some(backup), None, true) = (&mut run.backup, &target, write) {
+        // Snapshot real files before they change
+        match backup.save(&path) {
+            Ok(entry) => saved = entry.is_some(),
+            Err(e) => {
+                action = Action::Block(Errno::from_i32(-failure(&e) as i32));
+                by = Some(String::from("backup"));
+            }
+        }
+    }
+    let allowed = action.allows();
 
     if args.show {
         // Log open call
-        eprint!("{}({:?}, {})", sys, path, mode);
+        eprint!("[{}] {}({:?}, {})", pid, sys, path, mode);
 
-        if !allowed {
-            eprint!(" BLOCKED");
-        } else if let Some(Action::Replace(new)) = action {
+        if let Action::Block(errno) = action {
+            eprint!(" BLOCKED ({:?})", errno);
+        } else if let Action::Discard = action {
+            eprint!(" DISCARDED");
+        } else if let Action::Virtual(content) = &action {
+            eprint!(" => {}", content);
+        } else if let Some(new) = &target {
             eprint!(" => {}", &new.to_string_lossy());
+        } else if saved {
+            eprint!(" BACKED UP");
+        }
+        if let Some(by) = by {
+            eprint!(" by {}", by);
         }
         eprintln!();
     }
 
-    if let Some(Action::Replace(new)) = action {
+    if let (Some(new), Action::Replace(_), false) = (&target, &action, mode == OpenType::Read) {
+        // Writes to fresh targets need their directories, failures surface from the open
+        if let Some(dir) = new.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+    }
+
+    if let Some(new) = &target {
         // Rewrite syscall path
-        redirect_path(pid, regs.rsp, sys.path(regs), &new)?;
+        redirect_path(pid, regs.rsp, sys.path(regs), new)?;
     }
 
     if !allowed {
-        // Set syscall to invalid value so it fails to open
+        // Set syscall to invalid value so it is skipped
         regs.orig_rax = -1i64 as u64;
     }
 
     ptrace::setregs(pid, *regs)?;
 
-    Ok(())
+    match action {
+        Action::Block(errno) => Ok(Some(-(errno as i64))),
+        _ => Ok(None),
+    }
+}
+
+/// Log and count what the rules would have made of an open in a dry run
+fn audit(
+    run: &mut Run,
+    pid: Pid,
+    call: &str,
+    action: &Action,
+    target: &Option<PathBuf>,
+    by: Option<String>,
+) {
+    let effect = match action.effect(target.as_deref()) {
+        Some(effect) => effect,
+        None => {
+            if run.args.show {
+                match by {
+                    Some(by) => eprintln!("[{}] {} by {}", pid, call, by),
+                    None => eprintln!("[{}] {}", pid, call),
+                }
+            }
+            return;
+        }
+    };
+
+    let by = by.unwrap_or_else(|| String::from("default"));
+    eprintln!("[{}] {} WOULD {} by {}", pid, call, effect, by);
+    *run.violations
+        .entry(by)
+        .or_default()
+        .entry(format!("{} {}", call, effect))
+        .or_default() += 1;
 }
 
-/// Start child process and begin intercepting its calls to open
+/// Handle child call changing the file tree during a transactional run
+///
+/// The change is made in the staging area and the call skipped, returns the
+/// value to return from it. Changes that cannot be staged, such as links,
+/// device nodes and owners, fail with `EXDEV` or `EPERM`. Outside of
+/// transactions and for descriptors of staged copies returns `None` and the
+/// call runs as usual.
+fn handle_change(pid: Pid, run: &Run, regs: &mut Regs) -> Result<Option<i64>> {
+    let transaction = match &run.transaction {
+        Some(transaction) => transaction,
+        None => return Ok(None),
+    };
+    use self::Syscall::*;
+    let sys = Syscall::from(regs.orig_rax);
+    let flag = sys.flag(regs);
+    let follow = [Truncate, Chmod, FchmodAt, Chown].contains(&sys);
+    let path = match sys.fd(regs) {
+        Some(fd) => {
+            let file = fs::read_link(format!("/proc/{}/fd/{}", pid, fd)).unwrap_or_default();
+            if file.starts_with(&transaction.dir) {
+                // Descriptors opened through the staging area change copies
+                return Ok(None);
+            }
+            file
+        }
+        None => unsafe { user_path(pid, sys.dirfd(regs), *sys.path(regs), follow)? },
+    };
+    let new = match sys.new_path(regs) {
+        Some((dirfd, addr)) => Some(unsafe { user_path(pid, dirfd, addr, false)? }),
+        None => None,
+    };
+
+    let res = match (sys, &new) {
+        (UnlinkAt, _) if flag & AT_REMOVEDIR as u64 != 0 => transaction.rmdir(&path),
+        (Rmdir, _) => transaction.rmdir(&path),
+        (Unlink, _) | (UnlinkAt, _) => transaction.unlink(&path),
+        (Mkdir, _) | (MkdirAt, _) => transaction.mkdir(&path),
+        (Truncate, _) => transaction.truncate(&path, sys.value(regs)),
+        (Chmod, _) | (FchmodAt, _) => transaction.chmod(&path, sys.value(regs) as u32),
+        (Link, _) | (LinkAt, _) => Err(std::io::Error::from_raw_os_error(EXDEV)),
+        (Symlink, _)
+        | (SymlinkAt, _)
+        | (Mknod, _)
+        | (MknodAt, _)
+        | (Fchmod, _)
+        | (Chown, _)
+        | (Lchown, _)
+        | (FchownAt, _)
+        | (Fchown, _) => Err(std::io::Error::from_raw_os_error(EPERM)),
+        (_, Some(new)) if flag & !(RENAME_NOREPLACE as u64) == 0 => {
+            transaction.rename(&path, new, flag != 0)
+        }
+        _ => Err(std::io::Error::from_raw_os_error(nix::libc::EINVAL)),
+    };
+    let ret = res.as_ref().map_or_else(failure, |_| 0);
+
+    if run.args.show {
+        eprint!("[{}] {}({:?}", pid, sys, path);
+        if let Some(new) = &new {
+            eprint!(", {:?}", new);
+        }
+        match res {
+            Ok(()) => eprintln!(") STAGED"),
+            Err(_) => eprintln!(") FAILED ({:?})", Errno::from_i32(-ret as i32)),
+        }
+    }
+
+    // Skip the real call
+    regs.orig_rax = -1i64 as u64;
+    ptrace::setregs(pid, *regs)?;
+
+    Ok(Some(ret))
+}
+
+/// Tracing state kept for each process and thread in the traced tree
+#[derive(Default)]
+struct Tracee {
+    /// Set once the initial `SIGSTOP` of a new tracee has been consumed
+    started: bool,
+    /// Value to return from the skipped syscall the tracee is in
+    ret: Option<i64>,
+}
+
+/// Returns true if `event` reports the creation of a new tracee
+fn is_spawn(event: i32) -> bool {
+    event == Event::PTRACE_EVENT_FORK as i32
+        || event == Event::PTRACE_EVENT_VFORK as i32
+        || event == Event::PTRACE_EVENT_CLONE as i32
+}
+
+/// Handle the seccomp stop of `pid` before a traced call
+///
+/// Skipped calls stop again at their exit to have their return value set.
+fn seccomp_stop(pid: Pid, run: &mut Run, tracee: &mut Tracee, handled: &mut usize) -> Result<()> {
+    let mut regs = ptrace::getregs(pid)?;
+    let ret = if Syscall::OPENS.contains(&Syscall::from(regs.orig_rax)) {
+        *handled += 1;
+        handle_open(pid, run, &mut regs)?
+    } else {
+        handle_change(pid, run, &mut regs)?
+    };
+    match ret {
+        Some(ret) => {
+            tracee.ret = Some(ret);
+            resume_to_exit(pid)
+        }
+        None => resume(pid, None),
+    }
+}
+
+/// Handle the syscall exit stop of `pid`, returning `ret` if it is set
+fn exit_stop(pid: Pid, ret: Option<i64>) -> Result<()> {
+    if let Some(ret) = ret {
+        let mut regs = ptrace::getregs(pid)?;
+        regs.rax = ret as u64;
+        ptrace::setregs(pid, regs)?;
+    }
+    resume(pid, None)
+}
+
+/// Checks if `err` came from a tracee that is gone
+///
+/// Threads die without notice when another calls `exit_group` while they are
+/// stopped, which fails the next `ptrace` call with `ESRCH`.
+fn is_gone(err: &Error) -> bool {
+    match err {
+        Error::OS { err } => {
+            err.downcast_ref::<nix::Error>() == Some(&nix::Error::Sys(Errno::ESRCH))
+        }
+        _ => false,
+    }
+}
+
+/// Resume `pid`, ignoring tracees that have already died
+fn resume(pid: Pid, sig: Option<Signal>) -> Result<()> {
+    match ptrace::cont(pid, sig) {
+        Err(nix::Error::Sys(Errno::ESRCH)) => Ok(()),
+        res => Ok(res?),
+    }
+}
+
+/// Resume `pid` until it exits its current syscall
+fn resume_to_exit(pid: Pid) -> Result<()> {
+    match ptrace::syscall(pid) {
+        Err(nix::Error::Sys(Errno::ESRCH)) => Ok(()),
+        res => Ok(res?),
+    }
+}
+
+/// Start child process and begin intercepting calls to open in its tree
 pub fn start(args: &Args) -> Result<()> {
-    // Fork off program
-    let pid = trace_child(&args.argv)?;
+    let id = run::id();
+    let transaction = match args.transaction {
+        Some(_) => Some(Transaction::create(&id)?),
+        None => None,
+    };
+    let backup = if args.backup {
+        Some(Backup::create(&id)?)
+    } else {
+        None
+    };
+    let mut run = Run {
+        args,
+        policy: args.policy.clone(),
+        contents: Store::new(&id),
+        id,
+        transaction,
+        backup,
+        accesses: Vec::new(),
+        trace: match &args.trace {
+            Some(file) => Some(fs::File::create(file)?),
+            None => None,
+        },
+        violations: BTreeMap::new(),
+    };
+
+    // Fork off program, tracing changes to the file tree only when staging them
+    let mut calls = Syscall::OPENS.to_vec();
+    if run.transaction.is_some() {
+        calls.extend_from_slice(Syscall::CHANGES);
+    }
+    let root = trace_child(&args.argv, &calls)?;
+
+    let mut tracees: HashMap<Pid, Tracee> = HashMap::new();
+    tracees.insert(
+        root,
+        Tracee {
+            started: true,
+            ..Tracee::default()
+        },
+    );
+    resume(root, None)?;
 
     let mut handled = 0;
-    loop {
-        // Syscall entrance
-        ptrace::cont(pid, None)?;
+    let mut spawned = 1;
+    let mut code = 0;
+    while !tracees.is_empty() {
+        let status = match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::__WALL)) {
+            Err(nix::Error::Sys(Errno::ECHILD)) => break,
+            status => status?,
+        };
 
         use nix::sys::wait::WaitStatus::*;
-        match waitpid(pid, None)? {
-            Exited(_, code) => {
-                if args.show {
-                    eprintln!("\nSUMMARY:\n{} open calls handled", handled);
+        let stop = match status {
+            Exited(pid, status) => {
+                tracees.remove(&pid);
+                if pid == root {
+                    code = status;
+                }
+                Ok(())
+            }
+            Signaled(pid, sig, _) => {
+                tracees.remove(&pid);
+                if pid == root {
+                    code = 128 + sig as i32;
                 }
-                process::exit(code);
+                Ok(())
+            }
+            PtraceEvent(pid, Signal::SIGTRAP, event)
+                if event == Event::PTRACE_EVENT_SECCOMP as i32 =>
+            {
+                let tracee = tracees.entry(pid).or_default();
+                seccomp_stop(pid, &mut run, tracee, &mut handled)
             }
-            PtraceEvent(_, Signal::SIGTRAP, _) => {
-                handled += 1;
-                let mut regs = ptrace::getregs(pid)?;
-                handle_open(pid, args, &mut regs)?;
+            PtraceSyscall(pid) => {
+                let ret = tracees.get_mut(&pid).and_then(|t| t.ret.take());
+                exit_stop(pid, ret)
+            }
+            PtraceEvent(pid, Signal::SIGTRAP, event) if is_spawn(event) => {
+                // New tracees start with a SIGSTOP that may arrive before this event
+                ptrace::getevent(pid)
+                    .map_err(Error::from)
+                    .and_then(|child| {
+                        tracees
+                            .entry(Pid::from_raw(child as i32))
+                            .or_insert_with(|| {
+                                spawned += 1;
+                                Tracee::default()
+                            });
+                        resume(pid, None)
+                    })
+            }
+            PtraceEvent(pid, Signal::SIGTRAP, _) => resume(pid, None),
+            Stopped(pid, sig) => {
+                let tracee = tracees.entry(pid).or_insert_with(|| {
+                    spawned += 1;
+                    Tracee::default()
+                });
+
+                if !tracee.started && sig == Signal::SIGSTOP {
+                    // Swallow attach stop
+                    tracee.started = true;
+                    resume(pid, None)
+                } else {
+                    // Forward signal
+                    resume(pid, Some(sig))
+                }
+            }
+            _ => Ok(()),
+        };
+
+        match stop {
+            Err(ref err) if is_gone(err) => {
+                // Another thread ended the process while this one was stopped,
+                // the root is kept until its exit status arrives
+                match status.pid() {
+                    Some(pid) if pid != root => {
+                        tracees.remove(&pid);
+                    }
+                    _ => (),
+                }
+            }
+            stop => stop?,
+        }
+    }
+
+    if args.show {
+        eprintln!(
+            "\nSUMMARY:\n{} open calls handled across {} tracees",
+            handled, spawned
+        );
+    }
+    if args.dry_run {
+        let total: usize = run
+            .violations
+            .values()
+            .flat_map(|opens| opens.values())
+            .sum();
+        eprintln!(
+            "\nDRY RUN:\n{} opens would have been blocked or redirected",
+            total
+        );
+        for (by, opens) in &run.violations {
+            eprintln!("{}:", by);
+            for (open, count) in opens {
+                eprintln!("\t{} (x{})", open, count);
             }
-            _ => (),
         }
     }
+    if let (Some(transaction), Some(resolution)) = (&run.transaction, args.transaction) {
+        transaction.review(&run.id, resolution)?;
+    }
+    if let Some(backup) = &run.backup {
+        backup.finish(&run.id)?;
+    }
+    if let Some(file) = &args.record {
+        let argv: Vec<_> = args
+            .argv
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        fs::write(file, record::generate(&run.accesses, &argv.join(" ")))?;
+        eprintln!(
+            "\nRecorded {} opens into {}",
+            run.accesses.len(),
+            file.display()
+        );
+    }
+
+    // Exiting skips destructors
+    drop(run);
+    process::exit(code);
 }
@@ -1,14 +1,46 @@
 #![allow(non_upper_case_globals)]
 extern crate nix;
 use nix::libc::user_regs_struct as Regs;
-use nix::libc::{SYS_open, SYS_openat};
+use nix::libc::{
+    SYS_chmod, SYS_chown, SYS_creat, SYS_fchmod, SYS_fchmodat, SYS_fchown, SYS_fchownat,
+    SYS_lchown, SYS_link, SYS_linkat, SYS_mkdir, SYS_mkdirat, SYS_mknod, SYS_mknodat, SYS_open,
+    SYS_openat, SYS_rename, SYS_renameat, SYS_renameat2, SYS_rmdir, SYS_symlink, SYS_symlinkat,
+    SYS_truncate, SYS_unlink, SYS_unlinkat, O_CREAT, O_TRUNC, O_WRONLY,
+};
 
 use std::fmt;
 
 /// Syscalls used by handler
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Syscall {
     Open = SYS_open as isize,
     OpenAt = SYS_openat as isize,
+    /// Same as `open` with `O_CREAT | O_WRONLY | O_TRUNC`
+    Creat = SYS_creat as isize,
+    Unlink = SYS_unlink as isize,
+    UnlinkAt = SYS_unlinkat as isize,
+    Rename = SYS_rename as isize,
+    RenameAt = SYS_renameat as isize,
+    RenameAt2 = SYS_renameat2 as isize,
+    Mkdir = SYS_mkdir as isize,
+    MkdirAt = SYS_mkdirat as isize,
+    Rmdir = SYS_rmdir as isize,
+    Symlink = SYS_symlink as isize,
+    SymlinkAt = SYS_symlinkat as isize,
+    Link = SYS_link as isize,
+    LinkAt = SYS_linkat as isize,
+    Truncate = SYS_truncate as isize,
+    Mknod = SYS_mknod as isize,
+    MknodAt = SYS_mknodat as isize,
+    Chmod = SYS_chmod as isize,
+    FchmodAt = SYS_fchmodat as isize,
+    /// Same as `chmod` on an open descriptor
+    Fchmod = SYS_fchmod as isize,
+    Chown = SYS_chown as isize,
+    Lchown = SYS_lchown as isize,
+    FchownAt = SYS_fchownat as isize,
+    /// Same as `chown` on an open descriptor
+    Fchown = SYS_fchown as isize,
 }
 
 impl fmt::Display for Syscall {
@@ -16,32 +48,167 @@ impl fmt::Display for Syscall {
         match *self {
             Syscall::Open => write!(f, "open"),
             Syscall::OpenAt => write!(f, "openat"),
+            Syscall::Creat => write!(f, "creat"),
+            Syscall::Unlink => write!(f, "unlink"),
+            Syscall::UnlinkAt => write!(f, "unlinkat"),
+            Syscall::Rename => write!(f, "rename"),
+            Syscall::RenameAt => write!(f, "renameat"),
+            Syscall::RenameAt2 => write!(f, "renameat2"),
+            Syscall::Mkdir => write!(f, "mkdir"),
+            Syscall::MkdirAt => write!(f, "mkdirat"),
+            Syscall::Rmdir => write!(f, "rmdir"),
+            Syscall::Symlink => write!(f, "symlink"),
+            Syscall::SymlinkAt => write!(f, "symlinkat"),
+            Syscall::Link => write!(f, "link"),
+            Syscall::LinkAt => write!(f, "linkat"),
+            Syscall::Truncate => write!(f, "truncate"),
+            Syscall::Mknod => write!(f, "mknod"),
+            Syscall::MknodAt => write!(f, "mknodat"),
+            Syscall::Chmod => write!(f, "chmod"),
+            Syscall::FchmodAt => write!(f, "fchmodat"),
+            Syscall::Fchmod => write!(f, "fchmod"),
+            Syscall::Chown => write!(f, "chown"),
+            Syscall::Lchown => write!(f, "lchown"),
+            Syscall::FchownAt => write!(f, "fchownat"),
+            Syscall::Fchown => write!(f, "fchown"),
         }
     }
 }
 
 impl Syscall {
+    /// Calls that open files
+    pub const OPENS: &'static [Syscall] = &[Syscall::Open, Syscall::OpenAt, Syscall::Creat];
+
+    /// Calls that change the file tree without opening files
+    pub const CHANGES: &'static [Syscall] = &[
+        Syscall::Unlink,
+        Syscall::UnlinkAt,
+        Syscall::Rename,
+        Syscall::RenameAt,
+        Syscall::RenameAt2,
+        Syscall::Mkdir,
+        Syscall::MkdirAt,
+        Syscall::Rmdir,
+        Syscall::Symlink,
+        Syscall::SymlinkAt,
+        Syscall::Link,
+        Syscall::LinkAt,
+        Syscall::Truncate,
+        Syscall::Mknod,
+        Syscall::MknodAt,
+        Syscall::Chmod,
+        Syscall::FchmodAt,
+        Syscall::Fchmod,
+        Syscall::Chown,
+        Syscall::Lchown,
+        Syscall::FchownAt,
+        Syscall::Fchown,
+    ];
+
+    /// Address of the path the call acts on, the new entry for `symlink`
+    ///
+    /// Calls on descriptors have no path, see `fd`.
     pub fn path<'a>(&self, regs: &'a mut Regs) -> &'a mut u64 {
         use self::Syscall::*;
         match self {
-            Open => &mut regs.rdi,
-            OpenAt => &mut regs.rsi,
+            Open | Creat | Unlink | Rename | Mkdir | Rmdir | Link | Truncate | Mknod | Chmod
+            | Chown | Lchown => &mut regs.rdi,
+            OpenAt | UnlinkAt | RenameAt | RenameAt2 | MkdirAt | LinkAt | MknodAt | FchmodAt
+            | FchownAt | Symlink => &mut regs.rsi,
+            SymlinkAt => &mut regs.rdx,
+            Fchmod | Fchown => unreachable!("{} takes a descriptor rather than a path", self),
+        }
+    }
+
+    pub fn dirfd(&self, regs: &Regs) -> Option<i32> {
+        use self::Syscall::*;
+        match *self {
+            Open | Creat | Unlink | Rename | Mkdir | Rmdir | Link | Truncate | Mknod | Chmod
+            | Chown | Lchown | Symlink | Fchmod | Fchown => None,
+            OpenAt | UnlinkAt | RenameAt | RenameAt2 | MkdirAt | LinkAt | MknodAt | FchmodAt
+            | FchownAt => Some(regs.rdi as i32),
+            SymlinkAt => Some(regs.rsi as i32),
+        }
+    }
+
+    /// Descriptor of the file a call without path acts on
+    pub fn fd(&self, regs: &Regs) -> Option<i32> {
+        match *self {
+            Syscall::Fchmod | Syscall::Fchown => Some(regs.rdi as i32),
+            _ => None,
         }
     }
 
+    /// Directory descriptor and address of the destination of a rename or
+    /// the new name of a link
+    pub fn new_path(&self, regs: &Regs) -> Option<(Option<i32>, u64)> {
+        use self::Syscall::*;
+        match *self {
+            Rename | Link => Some((None, regs.rsi)),
+            RenameAt | RenameAt2 | LinkAt => Some((Some(regs.rdx as i32), regs.r10)),
+            _ => None,
+        }
+    }
+
+    /// Flags of the call, 0 for calls without any
     pub fn flag(&self, regs: &Regs) -> u64 {
         use self::Syscall::*;
         match *self {
             Open => regs.rsi,
-            OpenAt => regs.rdx,
+            Creat => (O_CREAT | O_WRONLY | O_TRUNC) as u64,
+            OpenAt | UnlinkAt => regs.rdx,
+            RenameAt2 | LinkAt | FchownAt => regs.r8,
+            _ => 0,
         }
     }
 
+    /// Length of `truncate` or mode of `chmod` calls, 0 for other calls
+    pub fn value(&self, regs: &Regs) -> u64 {
+        use self::Syscall::*;
+        match *self {
+            Truncate | Chmod | Fchmod => regs.rsi,
+            FchmodAt => regs.rdx,
+            _ => 0,
+        }
+    }
+
+    /// Look up a call handled by noop by its name, such as `openat`
+    pub fn parse(name: &str) -> Option<Syscall> {
+        Syscall::OPENS
+            .iter()
+            .chain(Syscall::CHANGES)
+            .find(|sys| sys.to_string() == name)
+            .cloned()
+    }
+
     pub fn from(d: u64) -> Syscall {
         use self::Syscall::*;
         match d as i64 {
             SYS_open => Open,
             SYS_openat => OpenAt,
+            SYS_creat => Creat,
+            SYS_unlink => Unlink,
+            SYS_unlinkat => UnlinkAt,
+            SYS_rename => Rename,
+            SYS_renameat => RenameAt,
+            SYS_renameat2 => RenameAt2,
+            SYS_mkdir => Mkdir,
+            SYS_mkdirat => MkdirAt,
+            SYS_rmdir => Rmdir,
+            SYS_symlink => Symlink,
+            SYS_symlinkat => SymlinkAt,
+            SYS_link => Link,
+            SYS_linkat => LinkAt,
+            SYS_truncate => Truncate,
+            SYS_mknod => Mknod,
+            SYS_mknodat => MknodAt,
+            SYS_chmod => Chmod,
+            SYS_fchmodat => FchmodAt,
+            SYS_fchmod => Fchmod,
+            SYS_chown => Chown,
+            SYS_lchown => Lchown,
+            SYS_fchownat => FchownAt,
+            SYS_fchown => Fchown,
             _ => panic!("No mapping from primitive to Syscall"),
         }
     }
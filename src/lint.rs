@@ -0,0 +1,323 @@
+//! Checks for policy rules that are dead, conflicting or likely typos
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::policy::{Pattern, Policy, Rule, Source, GLOB_META};
+use crate::types::Action;
+
+/// How serious a problem is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    /// Rule that may be intended but deserves a look
+    Warning,
+    /// Rule that cannot do what it says
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Warning => write!(f, "warning"),
+            Level::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Problem found with a single rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub level: Level,
+    pub source: Source,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.source, self.level, self.message)
+    }
+}
+
+/// Paths standing in for everything `pattern` matches
+///
+/// Globs get one path per `{a,b}` alternative, with wildcards filled in.
+/// Regular expressions give none, they are too open to guess at.
+fn samples(pattern: &Pattern) -> Vec<PathBuf> {
+    match pattern {
+        Pattern::Exact(p) => vec![p.clone()],
+        Pattern::Tree(p) => vec![p.clone(), p.join("noop-lint")],
+        Pattern::Glob(src, _) => expand_glob(src).into_iter().map(PathBuf::from).collect(),
+        Pattern::Regex(..) => Vec::new(),
+    }
+    .into_iter()
+    .filter(|path| pattern.matches(path))
+    .collect()
+}
+
+/// Fill the wildcards of `glob` in, once for each brace alternative
+fn expand_glob(glob: &str) -> Vec<String> {
+    if let Some(start) = glob.find('{') {
+        if let Some(len) = glob[start..].find('}') {
+            let end = start + len;
+            return glob[start + 1..end]
+                .split(',')
+                .flat_map(|alt| {
+                    expand_glob(&format!("{}{}{}", &glob[..start], alt, &glob[end + 1..]))
+                })
+                .collect();
+        }
+    }
+
+    let mut out = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                while chars.peek() == Some(&'*') {
+                    chars.next();
+                }
+                out.push('x');
+            }
+            '?' => out.push('x'),
+            '[' => {
+                let class: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                match class.chars().next() {
+                    Some('!') | None => out.push('x'),
+                    Some(c) => out.push(c),
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    vec![out]
+}
+
+/// Checks if `other` matches every path `pattern` does
+///
+/// Only shown for a tree holding the literal prefix of `pattern`, or a glob
+/// ending in `**` after a literal prefix of it. Regular expressions and
+/// patterns matching anywhere are never known to be contained.
+fn contains(other: &Pattern, pattern: &Pattern) -> bool {
+    let literal = match pattern {
+        Pattern::Regex(..) => return false,
+        _ => pattern.literal(),
+    };
+    if literal.as_os_str().is_empty() {
+        return false;
+    }
+
+    match other {
+        Pattern::Tree(dir) => literal.starts_with(dir),
+        Pattern::Glob(src, _) if src.ends_with("**") => {
+            let prefix = &src[..src.len() - 2];
+            !prefix.contains(|c: char| GLOB_META.contains(c))
+                && literal.to_string_lossy().starts_with(prefix)
+        }
+        _ => false,
+    }
+}
+
+/// Rule that wins every `open` the rule at index `i` could decide, and
+/// whether that is certain
+///
+/// Such a rule ranks higher and covers all modes of the shadowed one. It is
+/// certain if its pattern is the same or contains the other one, otherwise
+/// it is only seen to match every sample of the other pattern.
+fn shadow(policy: &Policy, i: usize) -> Option<(&Rule, bool)> {
+    let rule = &policy.rules[i];
+    let samples = samples(&rule.pattern);
+
+    policy
+        .rules
+        .iter()
+        .enumerate()
+        .filter(|(j, other)| other.rank(*j) > rule.rank(i) && other.mode.covers(&rule.mode))
+        .filter_map(|(j, other)| {
+            if other.pattern == rule.pattern || contains(&other.pattern, &rule.pattern) {
+                Some((j, other, true))
+            } else if !samples.is_empty() && samples.iter().all(|path| other.pattern.matches(path))
+            {
+                Some((j, other, false))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(j, other, certain)| (*certain, other.rank(*j)))
+        .map(|(_, other, certain)| (other, certain))
+}
+
+/// Directory that has to exist for the replacement `new` of `rule` to open
+fn target_dir(rule: &Rule, new: &Path) -> PathBuf {
+    let template = new.to_string_lossy();
+    if let Some(i) = template.find('{') {
+        // Only the part before the first placeholder is known
+        return match template[..i].rfind('/') {
+            Some(end) => PathBuf::from(&template[..=end]),
+            None => PathBuf::from("/"),
+        };
+    }
+    match rule.pattern {
+        Pattern::Tree(_) => new.to_path_buf(),
+        _ => new.parent().map(Path::to_path_buf).unwrap_or_default(),
+    }
+}
+
+/// Problems with the path `rule` matches, which `parse_path` keeps as given
+/// where it does not exist
+///
+/// Rules replacing or generating contents often name files that do not
+/// exist, so they are left alone.
+fn missing(rule: &Rule) -> Option<(Level, String)> {
+    if let Action::Replace(_) | Action::Virtual(_) = rule.action {
+        return None;
+    }
+
+    let literal = rule.pattern.literal();
+    if literal.as_os_str().is_empty() || literal.exists() {
+        return None;
+    }
+    match (&rule.pattern, literal.parent()) {
+        (Pattern::Exact(_), Some(dir)) | (Pattern::Tree(_), Some(dir)) if dir.is_dir() => Some((
+            Level::Warning,
+            format!("{} does not exist yet", literal.display()),
+        )),
+        _ => Some((
+            Level::Error,
+            format!(
+                "{} does not exist, so the rule matches nothing",
+                literal.display()
+            ),
+        )),
+    }
+}
+
+/// Find the problems of each rule of `policy`
+pub fn lint(policy: &Policy) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (i, rule) in policy.rules.iter().enumerate() {
+        let mut report = |level, message| {
+            problems.push(Problem {
+                level,
+                source: rule.source.clone(),
+                message,
+            })
+        };
+
+        if let Some((other, certain)) = shadow(policy, i) {
+            if other.pattern == rule.pattern {
+                let message = format!(
+                    "{} is also set at {}, which wins",
+                    rule.pattern, other.source
+                );
+                report(Level::Warning, message);
+            } else if certain {
+                let message = format!(
+                    "never applies, {} ({}) wins every match",
+                    other, other.source
+                );
+                report(Level::Error, message);
+            } else {
+                let message = format!(
+                    "may never apply, {} ({}) wins every path tried",
+                    other, other.source
+                );
+                report(Level::Warning, message);
+            }
+        }
+
+        if let Some((level, message)) = missing(rule) {
+            report(level, message);
+        }
+
+        if let Action::Replace(new) = &rule.action {
+            let dir = target_dir(rule, new);
+            if !dir.is_dir() {
+                let message = format!("redirect directory {} does not exist", dir.display());
+                report(Level::Warning, message);
+            }
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+
+    /// Levels and messages of the problems of `rules`
+    fn problems(rules: &[&str]) -> Vec<(Level, String)> {
+        let mut policy = Policy::default();
+        for (i, rule) in rules.iter().enumerate() {
+            policy.push(Rule::parse(rule, Source::Arg(i)).unwrap());
+        }
+        lint(&policy)
+            .into_iter()
+            .map(|problem| (problem.level, problem.message))
+            .collect()
+    }
+
+    /// Test finding rules that never apply
+    #[test]
+    fn shadowed() {
+        let tmp = env::temp_dir();
+        let tmp = tmp.display();
+        assert_eq!(
+            problems(&[&format!("{}/", tmp), &format!("{}/:allow", tmp)]),
+            vec![(
+                Level::Warning,
+                format!("{}/ is also set at argument 1, which wins", tmp)
+            )]
+        );
+        assert_eq!(
+            problems(&[&format!("{}/*.log", tmp), &format!("{}/**:allow", tmp)]),
+            vec![(
+                Level::Error,
+                format!(
+                    "never applies, {}/**:allow (argument 1) wins every match",
+                    tmp
+                )
+            )]
+        );
+
+        // Sampled globs are only suspected to be shadowed
+        for (rule, other) in &[
+            ("*.log", "*:allow"),
+            ("*.log", "x*:allow"),
+            ("[ab]*", "a*:allow"),
+        ] {
+            let (rule, other) = (format!("{}/{}", tmp, rule), format!("{}/{}", tmp, other));
+            let found = problems(&[&rule, &other]);
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].0, Level::Warning);
+            assert!(found[0].1.starts_with("may never apply"));
+        }
+
+        // Narrower rules win over broader ones, and modes are separate
+        assert!(problems(&[&format!("{}/*", tmp), &format!("{}/*.log", tmp)]).is_empty());
+        assert!(problems(&[&format!("{}/:r", tmp), &format!("{}/:w", tmp)]).is_empty());
+    }
+
+    /// Test flagging paths and redirect targets that do not exist
+    #[test]
+    fn missing() {
+        assert_eq!(
+            problems(&["/noop-lint/file"]),
+            vec![(
+                Level::Error,
+                String::from("/noop-lint/file does not exist, so the rule matches nothing")
+            )]
+        );
+        assert_eq!(problems(&["/noop-lint"])[0].0, Level::Warning);
+        assert_eq!(
+            problems(&["/noop-lint=/tmp/noop-lint-missing/{exe}"]),
+            vec![(
+                Level::Warning,
+                String::from("redirect directory /tmp/noop-lint-missing/ does not exist")
+            )]
+        );
+        assert!(problems(&["/tmp/x=/tmp/y", "/noop-lint=@text:hi"]).is_empty());
+    }
+}
@@ -1,12 +1,33 @@
 //! Main entrypoint to binary
 #![feature(try_trait)]
+// `str::strip_prefix` is newer than some nightlies with `try_trait`
+#![allow(clippy::manual_strip)]
 
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process;
 
 mod args;
+mod ask;
+mod backup;
+mod cases;
+mod content;
+mod diff;
 mod err;
+mod eval;
+mod expand;
+mod explain;
 mod intercept;
+mod lint;
+mod overlay;
+mod policy;
+mod record;
+mod resolve;
+mod run;
+mod strace;
+mod trace;
+mod transaction;
 mod types;
 
 fn main() {
@@ -15,11 +36,121 @@ fn main() {
             eprintln!("Malformed arguments: {}\n", e);
             args::usage(1);
         }
-        Ok(args) => {
-            if let Err(e) = intercept::start(&args) {
+        Ok(command) => {
+            let res = match command {
+                args::Command::Run(args) => intercept::start(&args),
+                args::Command::Commit(id) => commit(&id),
+                args::Command::Restore(id) => restore(&id),
+                args::Command::Eval(policy, trace) => eval::eval(&policy, &trace),
+                args::Command::Diff(old, new, json) => diff::diff(&old, &new, json),
+                args::Command::Explain(files, path, mode) => explain(&files, &path, &mode),
+                args::Command::Lint(files) => lint(&files),
+                args::Command::Import(log) => import(&log),
+                args::Command::Generate(trace, policy) => generate(&trace, &policy),
+                args::Command::Test(policy, cases) => test(&policy, &cases),
+            };
+            if let Err(e) = res {
                 eprintln!("Error: {}", e);
                 process::exit(1);
             }
         }
     }
 }
+
+/// Apply the changes kept by transactional run `id`
+fn commit(id: &str) -> err::Result<()> {
+    for change in transaction::Transaction::open(id)?.commit()? {
+        println!("{}", change);
+    }
+    Ok(())
+}
+
+/// Roll back the writes of run `id` from its backup
+fn restore(id: &str) -> err::Result<()> {
+    for entry in backup::Backup::open(id)?.restore()? {
+        println!("{}", entry);
+    }
+    Ok(())
+}
+
+/// Load the policy made of policy files `files`, in order
+fn load(files: &[PathBuf]) -> err::Result<policy::Policy> {
+    let mut policy = policy::Policy::default();
+    for file in files {
+        policy.include(file)?;
+    }
+    Ok(policy)
+}
+
+/// Show how the policy of `files` decides an open of `path` in `mode`
+fn explain(files: &[PathBuf], path: &Path, mode: &types::OpenType) -> err::Result<()> {
+    print!("{}", explain::explain(&load(files)?, path, mode));
+    Ok(())
+}
+
+/// Report the problems of the policy of `files`, exiting with 1 if any is
+/// an error
+fn lint(files: &[PathBuf]) -> err::Result<()> {
+    let problems = lint::lint(&load(files)?);
+    for problem in &problems {
+        println!("{}", problem);
+    }
+
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.level == lint::Level::Error)
+        .count();
+    println!("{} errors, {} warnings", errors, problems.len() - errors);
+    if errors > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Check the cases of file `cases` against policy file `policy`, exiting with
+/// 1 if any fails
+fn test(policy: &Path, cases: &Path) -> err::Result<()> {
+    let mut rules = policy::Policy::default();
+    rules.include(policy)?;
+
+    let mut failed = 0;
+    let all = cases::parse(&fs::read_to_string(cases)?, cases)?;
+    for case in &all {
+        if let Some(got) = case.check(&rules) {
+            println!(
+                "{}:{}: {}, got {}",
+                cases.display(),
+                case.line,
+                case.text,
+                got
+            );
+            failed += 1;
+        }
+    }
+    println!("{} passed, {} failed", all.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Print the accesses of strace log `log` as a trace
+fn import(log: &Path) -> err::Result<()> {
+    for access in strace::import(&fs::read_to_string(log)?) {
+        println!("{}", access.to_json());
+    }
+    Ok(())
+}
+
+/// Write a policy allowing the accesses of `trace` to `policy`
+fn generate(trace: &Path, policy: &Path) -> err::Result<()> {
+    let accesses = trace::parse(&fs::read_to_string(trace)?)?;
+    let from = format!("trace {}", trace.display());
+    fs::write(policy, record::generate(&accesses, &from))?;
+    eprintln!(
+        "Recorded {} opens into {}",
+        accesses.len(),
+        policy.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,90 @@
+//! Copy-on-write overlay keeping writes away from the real files
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::types::OpenType;
+
+/// Directory mirroring the file system that holds copies of written files
+#[derive(Debug)]
+pub struct Overlay {
+    pub dir: PathBuf,
+}
+
+impl Overlay {
+    pub fn new(dir: PathBuf) -> Overlay {
+        Overlay { dir }
+    }
+
+    /// Location of the copy of absolute `path` in the overlay
+    pub fn copy_of(&self, path: &Path) -> PathBuf {
+        self.dir.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// Path an `open` of `path` in `mode` uses instead, if any
+    ///
+    /// Writes go to a copy of the original, made on the first write, and
+    /// reads use that copy once it exists. Only regular files and files that
+    /// do not exist yet are overlaid, so devices such as `/dev/null` and
+    /// directory listings keep working.
+    pub fn target(&self, path: &Path, mode: &OpenType) -> io::Result<Option<PathBuf>> {
+        if path.starts_with(&self.dir) {
+            return Ok(None);
+        }
+
+        let copy = self.copy_of(path);
+        if copy.is_file() {
+            return Ok(Some(copy));
+        } else if *mode == OpenType::Read {
+            return Ok(None);
+        }
+
+        let original = match fs::metadata(path) {
+            Ok(meta) if meta.is_file() => true,
+            Ok(_) => return Ok(None),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        if let Some(dir) = copy.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if original {
+            fs::copy(path, &copy)?;
+        }
+
+        Ok(Some(copy))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    /// Test that writes copy the original and later reads see the copy
+    #[test]
+    fn copy_on_write() {
+        let tmp = env::temp_dir().join(format!("noop-overlay-{}", process::id()));
+        let file = tmp.join("file");
+        let overlay = Overlay::new(tmp.join("overlay"));
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(&file, "real").unwrap();
+
+        let read = overlay.target(&file, &OpenType::Read).unwrap();
+        let write = overlay.target(&file, &OpenType::Write).unwrap();
+        let copy = overlay.copy_of(&file);
+        let reread = overlay.target(&file, &OpenType::Read).unwrap();
+        let contents = fs::read_to_string(&copy);
+        let dev = overlay.target(Path::new("/dev/null"), &OpenType::Write);
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(read, None);
+        assert_eq!(write, Some(copy.clone()));
+        assert_eq!(reread, Some(copy));
+        assert_eq!(contents.unwrap(), "real");
+        assert_eq!(dev.unwrap(), None);
+    }
+}
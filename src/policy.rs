@@ -0,0 +1,931 @@
+//! Rules deciding what happens to each `open` and how they take precedence
+
+extern crate nix;
+use nix::errno::Errno;
+
+extern crate regex;
+use regex::Regex;
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::args::parse_path;
+use crate::content::Content;
+use crate::err::{Error, Result};
+use crate::expand::expand;
+use crate::resolve;
+use crate::types::{Action, OpenType};
+
+/// Target of discarded opens
+static DEV_NULL: &str = "/dev/null";
+
+/// Characters that make a rule path a glob
+pub static GLOB_META: &str = "*?[{";
+
+/// Characters that keep a path from being written as a plain rule
+static SPECIAL: &str = "*?[{=$#:\\";
+
+/// Action for writes to generated contents, which are shared between opens
+static READ_ONLY: Action = Action::Block(Errno::EROFS);
+
+/// Characters that end the literal prefix of a regular expression
+static REGEX_META: &str = ".^$*+?()[]{}|\\";
+
+/// Set of paths a rule applies to
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// A single path
+    Exact(PathBuf),
+    /// A directory and everything below it
+    Tree(PathBuf),
+    /// Shell glob supporting `**` and `{a,b}`, with its compiled form
+    Glob(String, Regex),
+    /// Regular expression anchored at the start of the path
+    Regex(String, Regex),
+}
+
+impl Pattern {
+    /// Parse pattern from the path part of a rule
+    ///
+    /// Names starting with `re:` are regular expressions and names containing
+    /// any of `*?[{` are globs. Relative globs are anchored at the current
+    /// directory unless they start with `**`, which matches anywhere.
+    pub fn parse(name: &str) -> Result<Pattern> {
+        if name.starts_with("re:") {
+            let src = &name[3..];
+            let re = Regex::new(&format!("^(?:{})", src))?;
+            return Ok(Pattern::Regex(src.to_string(), re));
+        }
+
+        let meta = match name.find(|c: char| GLOB_META.contains(c)) {
+            Some(meta) => meta,
+            None if name.ends_with('/') => return Ok(Pattern::Tree(parse_path(name))),
+            None => return Ok(Pattern::Exact(parse_path(name))),
+        };
+
+        let mut glob = if name.starts_with("**") {
+            name.to_string()
+        } else {
+            // Resolve literal directory prefix like any other path
+            let (dir, rest) = match name[..meta].rfind('/') {
+                Some(0) => (PathBuf::from("/"), &name[1..]),
+                Some(i) => (parse_path(&name[..i]), &name[i + 1..]),
+                None => (parse_path("."), name),
+            };
+            dir.join(rest).to_string_lossy().into_owned()
+        };
+        if glob.ends_with('/') {
+            glob.push_str("**");
+        }
+
+        let re = Regex::new(&glob_regex(&glob))?;
+        Ok(Pattern::Glob(glob, re))
+    }
+
+    /// Checks if `path` is covered by the pattern
+    pub fn matches(&self, path: &Path) -> bool {
+        match self {
+            Pattern::Exact(p) => path == p,
+            Pattern::Tree(p) => path.starts_with(p),
+            Pattern::Glob(_, re) | Pattern::Regex(_, re) => re.is_match(&path.to_string_lossy()),
+        }
+    }
+
+    /// Rank used to order overlapping patterns, higher is more specific
+    ///
+    /// Deeper paths are more specific. For globs and regular expressions the
+    /// depth is that of their literal prefix. At the same depth a single file
+    /// beats a glob or regular expression, which beats a whole tree.
+    fn specificity(&self) -> (usize, u8) {
+        match self {
+            Pattern::Exact(p) => (p.components().count(), 2),
+            Pattern::Glob(src, _) => (literal_depth(src, GLOB_META), 1),
+            Pattern::Regex(src, _) => (literal_depth(src.trim_start_matches('^'), REGEX_META), 1),
+            Pattern::Tree(p) => (p.components().count(), 0),
+        }
+    }
+
+    /// Literal path every matched path starts with
+    ///
+    /// Empty for patterns matching anywhere.
+    pub fn literal(&self) -> PathBuf {
+        match self {
+            Pattern::Exact(p) | Pattern::Tree(p) => p.clone(),
+            Pattern::Glob(src, _) => PathBuf::from(literal_dir(src, GLOB_META)),
+            Pattern::Regex(src, _) => {
+                PathBuf::from(literal_dir(src.trim_start_matches('^'), REGEX_META))
+            }
+        }
+    }
+
+    /// Part of matched `path` below the literal directory of the pattern
+    ///
+    /// This is the file name for single paths and the path relative to the
+    /// tree for trees. Patterns matching anywhere give the whole path.
+    pub fn relative(&self, path: &Path) -> PathBuf {
+        let dir = match self {
+            Pattern::Exact(p) => p.parent().map(Path::to_path_buf).unwrap_or_default(),
+            _ => self.literal(),
+        };
+        let rel = path.strip_prefix(&dir).unwrap_or(path);
+        rel.strip_prefix("/").unwrap_or(rel).to_path_buf()
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Exact(p) => write!(f, "{}", p.display()),
+            Pattern::Tree(p) if p.parent().is_none() => write!(f, "{}", p.display()),
+            Pattern::Tree(p) => write!(f, "{}/", p.display()),
+            Pattern::Glob(src, _) => write!(f, "{}", src),
+            Pattern::Regex(src, _) => write!(f, "re:{}", src),
+        }
+    }
+}
+
+/// Leading directories of `src` before the first of `meta`, with trailing `/`
+fn literal_dir<'a>(src: &'a str, meta: &str) -> &'a str {
+    let end = src.find(|c: char| meta.contains(c)).unwrap_or(src.len());
+    match src[..end].rfind('/') {
+        Some(i) => &src[..=i],
+        None => "",
+    }
+}
+
+/// Number of complete path components before the first of `meta` in `src`
+fn literal_depth(src: &str, meta: &str) -> usize {
+    literal_dir(src, meta).matches('/').count()
+}
+
+/// Checks if `path` holds characters with a meaning in rules
+pub fn is_special(path: &Path) -> bool {
+    path.to_string_lossy()
+        .contains(|c: char| SPECIAL.contains(c) || c.is_whitespace())
+}
+
+/// Pattern matching exactly `path`
+///
+/// Paths that would not survive as a plain rule become regular expressions
+/// with escaped characters.
+pub fn exact(path: &Path) -> String {
+    if !is_special(path) {
+        return path.display().to_string();
+    }
+
+    let mut re = String::from("re:^");
+    for c in path.to_string_lossy().chars() {
+        if c.is_alphanumeric() || c == '/' || c == '_' || c == '-' {
+            re.push(c);
+        } else if c.is_ascii() {
+            re.push_str(&format!("\\x{:02x}", c as u32));
+        } else {
+            re.push(c);
+        }
+    }
+    re.push('$');
+    re
+}
+
+/// Translate shell glob into an anchored regular expression
+///
+/// `*` and `?` stay within one component, `**` crosses components and
+/// `{a,b}` matches either alternative.
+fn glob_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut braces = 0;
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    // Zero or more directories
+                    chars.next();
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                re.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    re.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == '\\' || c == '[' {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            '{' => {
+                braces += 1;
+                re.push_str("(?:");
+            }
+            ',' if braces > 0 => re.push('|'),
+            '}' if braces > 0 => {
+                braces -= 1;
+                re.push(')');
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+
+    re
+}
+
+/// Place a rule was declared
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// Position in the command line arguments
+    Arg(usize),
+    /// Policy file and line number
+    File(PathBuf, usize),
+    /// Answer to an `--ask` question that was not saved
+    Answer,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Arg(i) => write!(f, "argument {}", i),
+            Source::File(path, line) => write!(f, "{}:{}", path.display(), line),
+            Source::Answer => write!(f, "answer"),
+        }
+    }
+}
+
+/// Single rule, as given by a `PATTERN[:rw][:allow|:discard|:ERRNO]`,
+/// `PATTERN[:rw]=REPLACE` or `PATTERN[:r]=@KIND:ARG` argument
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub pattern: Pattern,
+    pub mode: OpenType,
+    pub action: Action,
+    pub source: Source,
+    pub comment: Option<String>,
+}
+
+impl Rule {
+    /// Precedence of the rule at index `i` over other matching rules
+    ///
+    /// Higher ranks win, see `Policy` for the order.
+    pub fn rank(&self, i: usize) -> ((usize, u8), bool, usize) {
+        let single = self.mode != OpenType::All;
+        (self.pattern.specificity(), single, i)
+    }
+
+    /// Parse rule from its command line form
+    pub fn parse(arg: &str, source: Source) -> Result<Rule> {
+        // Only the first = separates, generated contents may contain more
+        let parts: Vec<&str> = arg.splitn(2, '=').collect();
+
+        let (name, mode, action) = if parts.len() == 2 {
+            // Replace, optionally for a single mode
+            let (name, mode) = suffix(parts[0], OpenType::parse);
+            if parts[1].starts_with('@') {
+                let content = Content::parse(parts[1])?;
+                // Generated contents are shared by every open, so read-only
+                let mode = match mode {
+                    None | Some(OpenType::Read) => OpenType::Read,
+                    Some(_) => {
+                        return Err(Error::Arg {
+                            reason: "Generated contents only apply to reads",
+                        })
+                    }
+                };
+                (name, mode, Action::Virtual(content))
+            } else {
+                let mode = mode.unwrap_or(OpenType::All);
+                (name, mode, Action::Replace(PathBuf::from(&parts[1])))
+            }
+        } else {
+            let (name, action) = suffix(parts[0], Action::parse);
+            let (name, mode) = suffix(name, OpenType::parse);
+            let mode = mode.unwrap_or(OpenType::All);
+            (name, mode, action.unwrap_or(Action::Block(Errno::EPERM)))
+        };
+
+        if name.is_empty() {
+            return Err(Error::Arg {
+                reason: "Empty path in rule",
+            });
+        }
+
+        let pattern = Pattern::parse(name)?;
+
+        Ok(Rule {
+            pattern,
+            mode,
+            action,
+            source,
+            comment: None,
+        })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern)?;
+        if self.mode != OpenType::All {
+            write!(f, ":{}", self.mode.to_string().to_lowercase())?;
+        }
+        match &self.action {
+            Action::Allow => write!(f, ":allow"),
+            Action::Block(Errno::EPERM) => Ok(()),
+            Action::Block(errno) => write!(f, ":{:?}", errno),
+            Action::Discard => write!(f, ":discard"),
+            Action::Replace(new) => write!(f, "={}", new.display()),
+            Action::Virtual(content) => write!(f, "={}", content),
+        }
+    }
+}
+
+/// Split a trailing `:TOKEN` off `name` if `parse` accepts `TOKEN`
+fn suffix<T, F>(name: &str, parse: F) -> (&str, Option<T>)
+where
+    F: Fn(&str) -> Option<T>,
+{
+    if let Some(i) = name.rfind(':') {
+        if let Some(value) = parse(&name[i + 1..]) {
+            return (&name[..i], Some(value));
+        }
+    }
+
+    (name, None)
+}
+
+/// Values filled into the `{...}` placeholders of replacement paths
+#[derive(Debug, Clone, Copy)]
+pub struct Vars<'a> {
+    /// Process making the call
+    pub pid: i32,
+    /// Name of the program making the call
+    pub exe: &'a str,
+    /// Identifier of this run of noop
+    pub run_id: &'a str,
+}
+
+/// Outcome of matching an `open` against a policy
+#[derive(Debug, Clone, Copy)]
+pub struct Decision<'a> {
+    /// Winning rule, `None` if the policy default applied
+    pub rule: Option<&'a Rule>,
+    pub action: &'a Action,
+}
+
+impl<'a> Decision<'a> {
+    /// What made the decision, `None` for opens the default lets through
+    pub fn by(&self) -> Option<String> {
+        match self.rule {
+            Some(rule) => Some(format!("{} ({})", rule.pattern, rule.source)),
+            None if !self.action.allows() => Some(String::from("default")),
+            None => None,
+        }
+    }
+
+    /// Path the `open` of `path` is sent to instead, if any
+    ///
+    /// Placeholders of the replacement are filled in from `vars` and `path`.
+    /// Replacements by tree rules then map the part of `path` below the tree
+    /// onto the target, like a bind mount, unless they place it themselves
+    /// with `{relpath}` or `{basename}`.
+    pub fn target(&self, path: &Path, vars: &Vars) -> Option<PathBuf> {
+        let new = match self.action {
+            Action::Replace(new) => new,
+            Action::Discard => return Some(PathBuf::from(DEV_NULL)),
+            _ => return None,
+        };
+        let rel = match self.rule {
+            Some(rule) => rule.pattern.relative(path),
+            None => PathBuf::new(),
+        };
+
+        let template = new.to_string_lossy();
+        let target = if template.contains('{') {
+            PathBuf::from(render(&template, path, &rel, vars))
+        } else {
+            new.clone()
+        };
+        let placed = template.contains("{relpath}") || template.contains("{basename}");
+        match self.rule.map(|rule| &rule.pattern) {
+            Some(Pattern::Tree(_)) if !placed => Some(target.join(rel)),
+            _ => Some(target),
+        }
+    }
+}
+
+/// Fill the placeholders of `template` for an `open` of `path`
+///
+/// Supported are `{pid}`, `{exe}`, `{basename}`, `{relpath}` and `{run_id}`,
+/// anything else is left as is.
+fn render(template: &str, path: &Path, rel: &Path, vars: &Vars) -> String {
+    let basename = path.file_name().unwrap_or_default().to_string_lossy();
+    template
+        .replace("{pid}", &vars.pid.to_string())
+        .replace("{exe}", vars.exe)
+        .replace("{basename}", &basename)
+        .replace("{relpath}", &rel.to_string_lossy())
+        .replace("{run_id}", vars.run_id)
+}
+
+/// Ordered collection of rules with defaults for unmatched paths
+///
+/// When several rules match a path the most specific pattern wins. Ties are
+/// broken in favor of rules for a single mode over rules for both, and then
+/// in favor of later rules.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+    /// Action for reads no rule matches
+    pub default_read: Action,
+    /// Action for writes no rule matches
+    pub default_write: Action,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            rules: Vec::new(),
+            default_read: Action::Allow,
+            default_write: Action::Allow,
+        }
+    }
+}
+
+impl Policy {
+    /// Set the action for unmatched accesses in `mode`
+    pub fn set_default(&mut self, mode: &OpenType, action: Action) {
+        if mode.covers(&OpenType::Read) {
+            self.default_read = action.clone();
+        }
+        if mode.covers(&OpenType::Write) {
+            self.default_write = action;
+        }
+    }
+
+    /// Action for unmatched accesses in `mode`
+    pub fn default_action(&self, mode: &OpenType) -> &Action {
+        match mode {
+            OpenType::Read => &self.default_read,
+            OpenType::Write => &self.default_write,
+            OpenType::All if !self.default_write.allows() => &self.default_write,
+            OpenType::All => &self.default_read,
+        }
+    }
+
+    /// Add `rule` after all existing rules
+    pub fn push(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    /// Add `rule` answering an `--ask` question after all existing rules
+    ///
+    /// The rule is appended to policy file `file` if given, so later runs
+    /// apply it too. Paths that are not plain rules are saved escaped.
+    pub fn learn(&mut self, mut rule: Rule, file: Option<&Path>) -> Result<()> {
+        if let Some(file) = file {
+            let mut saved = rule.clone();
+            if let Pattern::Exact(path) = &rule.pattern {
+                saved.pattern = Pattern::parse(&exact(path))?;
+            }
+            let text = fs::read_to_string(file).unwrap_or_default();
+            let mut out = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)?;
+            if !text.is_empty() && !text.ends_with('\n') {
+                writeln!(out)?;
+            }
+            writeln!(out, "{}", saved)?;
+            rule.source = Source::File(resolve::canonicalize(file), text.lines().count() + 1);
+        }
+        self.push(rule);
+
+        Ok(())
+    }
+
+    /// Add rules from policy file at `path` after all existing rules
+    ///
+    /// Each line holds a rule in its command line form, optionally followed
+    /// by a `#` comment that is kept with the rule. `include FILE` adds the
+    /// rules of another file, relative to the including one, and
+    /// `default MODE ACTION` sets the action for unmatched paths. `~`,
+    /// `$VAR` and `${VAR}` are expanded.
+    pub fn include(&mut self, path: &Path) -> Result<()> {
+        self.load(path, &mut Vec::new())
+    }
+
+    /// Load policy file, tracking the chain of includes in `stack`
+    fn load(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> Result<()> {
+        let path = resolve::canonicalize(path);
+        if stack.contains(&path) {
+            return Err(Error::Arg {
+                reason: "Policy file includes itself",
+            });
+        }
+
+        let text = fs::read_to_string(&path).map_err(|err| Error::Policy {
+            at: path.display().to_string(),
+            err: Box::new(err.into()),
+        })?;
+
+        stack.push(path.clone());
+        for (i, line) in text.lines().enumerate() {
+            let source = Source::File(path.clone(), i + 1);
+            self.line(line, source.clone(), stack)
+                .map_err(|err| Error::Policy {
+                    at: source.to_string(),
+                    err: Box::new(err),
+                })?;
+        }
+        stack.pop();
+
+        Ok(())
+    }
+
+    /// Parse a single policy file line declared at `source`
+    fn line(&mut self, line: &str, source: Source, stack: &mut Vec<PathBuf>) -> Result<()> {
+        // Comments start at a # beginning a word
+        let start = line
+            .char_indices()
+            .find(|&(i, c)| c == '#' && line[..i].chars().last().map_or(true, char::is_whitespace))
+            .map(|(i, _)| i);
+        let (line, comment) = match start {
+            Some(i) => (&line[..i], Some(line[i + 1..].trim().to_string())),
+            None => (line, None),
+        };
+
+        let line = expand(line.trim())?;
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        if line.starts_with("default ") {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let mode = words.get(1).and_then(|mode| OpenType::parse(mode));
+            let action = words.get(2).and_then(|action| Action::parse(action));
+            return match (mode, action) {
+                (Some(mode), Some(action)) if words.len() == 3 => {
+                    self.set_default(&mode, action);
+                    Ok(())
+                }
+                _ => Err(Error::Arg {
+                    reason: "Expected `default r|w|rw allow|block|ERRNO`",
+                }),
+            };
+        }
+
+        if line.starts_with("include ") {
+            let file = Path::new(line["include ".len()..].trim());
+            let file = match &source {
+                Source::File(parent, _) => parent.parent().unwrap_or(parent).join(file),
+                Source::Arg(_) | Source::Answer => file.to_path_buf(),
+            };
+            return self.load(&file, stack);
+        }
+
+        let mut rule = Rule::parse(&line, source)?;
+        rule.comment = comment;
+        self.push(rule);
+
+        Ok(())
+    }
+
+    /// Decide what happens to an `open` of `path` in `mode`
+    ///
+    /// A read-write open needs both accesses: a block on either blocks it,
+    /// otherwise the rule for writes takes priority over the one for reads.
+    /// Generated contents cannot be opened for writing and fail with `EROFS`.
+    pub fn lookup(&self, path: &Path, mode: &OpenType) -> Decision<'_> {
+        if *mode != OpenType::All {
+            return self.decide(path, mode);
+        }
+
+        let read = self.decide(path, &OpenType::Read);
+        let write = self.decide(path, &OpenType::Write);
+        if !write.action.allows() {
+            write
+        } else if let Action::Virtual(_) = read.action {
+            Decision {
+                rule: read.rule,
+                action: &READ_ONLY,
+            }
+        } else if !read.action.allows() {
+            read
+        } else if write.rule.is_some() {
+            write
+        } else {
+            read
+        }
+    }
+
+    /// Rules matching `path` for some access of `mode`, the preferred first
+    pub fn candidates(&self, path: &Path, mode: &OpenType) -> Vec<&Rule> {
+        let mut rules: Vec<_> = self
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| {
+                (rule.mode.covers(mode) || mode.covers(&rule.mode)) && rule.pattern.matches(path)
+            })
+            .collect();
+        rules.sort_by_key(|(i, rule)| Reverse(rule.rank(*i)));
+
+        rules.into_iter().map(|(_, rule)| rule).collect()
+    }
+
+    /// Decide a single access `mode`
+    fn decide(&self, path: &Path, mode: &OpenType) -> Decision<'_> {
+        match self.candidates(path, mode).into_iter().next() {
+            Some(rule) => Decision {
+                rule: Some(rule),
+                action: &rule.action,
+            },
+            None => Decision {
+                rule: None,
+                action: self.default_action(mode),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Action of rules without an explicit one
+    const BLOCK: Action = Action::Block(Errno::EPERM);
+
+    /// Build policy from command line style rules
+    fn policy(rules: &[&str]) -> Policy {
+        let mut policy = Policy::default();
+        for (i, rule) in rules.iter().enumerate() {
+            policy.push(Rule::parse(rule, Source::Arg(i)).unwrap());
+        }
+        policy
+    }
+
+    /// Returns the action taken for `path` opened in `mode`
+    fn action(policy: &Policy, path: &str, mode: OpenType) -> Action {
+        policy.lookup(Path::new(path), &mode).action.clone()
+    }
+
+    /// Returns where a read of `path` by `cat` with pid 7 is redirected
+    fn target(policy: &Policy, path: &str) -> Option<PathBuf> {
+        let vars = Vars {
+            pid: 7,
+            exe: "cat",
+            run_id: "run",
+        };
+        policy
+            .lookup(Path::new(path), &OpenType::Read)
+            .target(Path::new(path), &vars)
+    }
+
+    /// Test that directory rules cover their whole tree
+    #[test]
+    fn tree() {
+        let p = policy(&["/noop/a/"]);
+        assert_eq!(action(&p, "/noop/a", OpenType::Read), BLOCK);
+        assert_eq!(action(&p, "/noop/a/b/c", OpenType::Read), BLOCK);
+        assert_eq!(action(&p, "/noop/ab", OpenType::Read), Action::Allow);
+    }
+
+    /// Test that the most specific rule wins regardless of order
+    #[test]
+    fn specific() {
+        let p = policy(&["/noop/a/b/:allow", "/noop/a/"]);
+        assert_eq!(action(&p, "/noop/a/c", OpenType::Read), BLOCK);
+        assert_eq!(action(&p, "/noop/a/b/c", OpenType::Read), Action::Allow);
+
+        let p = policy(&["/noop/a/b", "/noop/a/b/:allow"]);
+        assert_eq!(action(&p, "/noop/a/b", OpenType::Read), BLOCK);
+    }
+
+    /// Test tie-breaking between modes and duplicates
+    #[test]
+    fn ties() {
+        let p = policy(&["/noop/a/:r:allow", "/noop/a/"]);
+        assert_eq!(action(&p, "/noop/a/b", OpenType::Read), Action::Allow);
+        assert_eq!(action(&p, "/noop/a/b", OpenType::Write), BLOCK);
+        assert_eq!(action(&p, "/noop/a/b", OpenType::All), BLOCK);
+
+        let p = policy(&["/noop/a:allow", "/noop/a"]);
+        assert_eq!(action(&p, "/noop/a", OpenType::Read), BLOCK);
+    }
+
+    /// Test glob patterns
+    #[test]
+    fn glob() {
+        let p = policy(&["/noop/**/*.log:w", "/noop/{a,b}/c"]);
+        assert_eq!(action(&p, "/noop/x.log", OpenType::Write), BLOCK);
+        assert_eq!(action(&p, "/noop/x/y/z.log", OpenType::Write), BLOCK);
+        assert_eq!(action(&p, "/noop/x/y/z.log", OpenType::Read), Action::Allow);
+        assert_eq!(
+            action(&p, "/noop/x/y/z.logs", OpenType::Write),
+            Action::Allow
+        );
+        assert_eq!(action(&p, "/noop/b/c", OpenType::Read), BLOCK);
+        assert_eq!(action(&p, "/noop/d/c", OpenType::Read), Action::Allow);
+
+        let p = policy(&["**/*.log"]);
+        assert_eq!(action(&p, "/anywhere/x.log", OpenType::Read), BLOCK);
+    }
+
+    /// Test regular expression patterns
+    #[test]
+    fn regex() {
+        let p = policy(&["re:^/noop/build-[0-9]+/"]);
+        assert_eq!(action(&p, "/noop/build-12/out", OpenType::Read), BLOCK);
+        assert_eq!(
+            action(&p, "/noop/build-x/out", OpenType::Read),
+            Action::Allow
+        );
+        assert_eq!(
+            action(&p, "/other/noop/build-1/", OpenType::Read),
+            Action::Allow
+        );
+    }
+
+    /// Test that globs are ranked between files and trees
+    #[test]
+    fn glob_specific() {
+        let p = policy(&["/noop/a/*.log:allow", "/noop/a/", "/noop/a/x.log"]);
+        assert_eq!(action(&p, "/noop/a/y.log", OpenType::Read), Action::Allow);
+        assert_eq!(action(&p, "/noop/a/x.log", OpenType::Read), BLOCK);
+    }
+
+    /// Test default actions for unmatched paths
+    #[test]
+    fn defaults() {
+        let mut p = policy(&["/noop/out/:w:allow", "/noop/in:r:allow"]);
+        p.set_default(&OpenType::Write, BLOCK);
+        assert_eq!(action(&p, "/noop/x", OpenType::Read), Action::Allow);
+        assert_eq!(action(&p, "/noop/x", OpenType::Write), BLOCK);
+        assert_eq!(action(&p, "/noop/out/x", OpenType::Write), Action::Allow);
+        assert_eq!(action(&p, "/noop/out/x", OpenType::All), Action::Allow);
+        assert_eq!(action(&p, "/noop/in", OpenType::All), BLOCK);
+
+        p.set_default(&OpenType::All, BLOCK);
+        assert_eq!(action(&p, "/noop/x", OpenType::Read), BLOCK);
+        assert_eq!(action(&p, "/noop/in", OpenType::Read), Action::Allow);
+    }
+
+    /// Test rules failing with a chosen errno
+    #[test]
+    fn errno() {
+        let p = policy(&["/noop/a:w:ENOENT", "/noop/a:EROFS"]);
+        let enoent = Action::Block(Errno::ENOENT);
+        assert_eq!(action(&p, "/noop/a", OpenType::Write), enoent);
+        assert_eq!(action(&p, "/noop/a", OpenType::All), enoent);
+        assert_eq!(
+            action(&p, "/noop/a", OpenType::Read),
+            Action::Block(Errno::EROFS)
+        );
+    }
+
+    /// Test separate replacements for reads and writes
+    #[test]
+    fn replace_mode() {
+        let p = policy(&["/noop/a:r=/noop/read", "/noop/a:w=/noop/write"]);
+        let read = Action::Replace(PathBuf::from("/noop/read"));
+        let write = Action::Replace(PathBuf::from("/noop/write"));
+        assert_eq!(action(&p, "/noop/a", OpenType::Read), read);
+        assert_eq!(action(&p, "/noop/a", OpenType::Write), write);
+        assert_eq!(action(&p, "/noop/a", OpenType::All), write);
+    }
+
+    /// Test remapping a tree onto another directory
+    #[test]
+    fn remap() {
+        let p = policy(&["/noop/cache/=/tmp/cache/"]);
+        assert_eq!(
+            target(&p, "/noop/cache/a/b"),
+            Some(PathBuf::from("/tmp/cache/a/b"))
+        );
+        assert_eq!(target(&p, "/noop/other"), None);
+    }
+
+    /// Test filling placeholders of replacement paths
+    #[test]
+    fn template() {
+        let p = policy(&[
+            "/noop/a={exe}.{pid}/{basename}",
+            "/noop/b/={run_id}/{relpath}",
+            "/noop/c/*.log=/logs/{relpath}",
+            "**/d={unknown}",
+            "/noop/e/=/tmp/cache-{pid}/",
+        ]);
+        assert_eq!(target(&p, "/noop/a"), Some(PathBuf::from("cat.7/a")));
+        assert_eq!(target(&p, "/noop/b/x/y"), Some(PathBuf::from("run/x/y")));
+        assert_eq!(
+            target(&p, "/noop/c/e.log"),
+            Some(PathBuf::from("/logs/e.log"))
+        );
+        assert_eq!(target(&p, "/x/d"), Some(PathBuf::from("{unknown}")));
+        assert_eq!(
+            target(&p, "/noop/e/f/g"),
+            Some(PathBuf::from("/tmp/cache-7/f/g"))
+        );
+    }
+
+    /// Test discarding only one mode
+    #[test]
+    fn discard() {
+        let p = policy(&["/noop/a:w:discard"]);
+        assert_eq!(action(&p, "/noop/a", OpenType::Write), Action::Discard);
+        assert_eq!(action(&p, "/noop/a", OpenType::All), Action::Discard);
+        assert_eq!(action(&p, "/noop/a", OpenType::Read), Action::Allow);
+    }
+
+    /// Test rules serving generated contents
+    #[test]
+    fn virtual_content() {
+        let rule = "/noop/hosts:r=@text:a=b\\n";
+        let p = policy(&[rule]);
+        let text = Content::Text(String::from("a=b\n"));
+        assert_eq!(
+            action(&p, "/noop/hosts", OpenType::Read),
+            Action::Virtual(text)
+        );
+        assert_eq!(target(&p, "/noop/hosts"), None);
+        assert_eq!(p.rules[0].to_string(), rule);
+    }
+
+    /// Test that learned rules are saved to the policy file
+    #[test]
+    fn learn() {
+        let file = std::env::temp_dir().join(format!("noop-learn-{}", std::process::id()));
+        fs::write(&file, "/noop/a").unwrap();
+        let mut p = Policy::default();
+        p.include(&file).unwrap();
+        let rule = Rule::parse("/noop/b:w:allow", Source::Answer).unwrap();
+        p.learn(rule, Some(&file)).unwrap();
+        let text = fs::read_to_string(&file);
+        let _ = fs::remove_file(&file);
+
+        assert_eq!(text.unwrap(), "/noop/a\n/noop/b:w:allow\n");
+        assert_eq!(p.rules[1].source, Source::File(file, 2));
+    }
+
+    /// Test that learned paths with special characters load back
+    #[test]
+    fn learn_special() {
+        let file = std::env::temp_dir().join(format!("noop-special-{}", std::process::id()));
+        let path = Path::new("/noop/a b=$HOME #c*[d]:w");
+        let rule = Rule {
+            pattern: Pattern::Exact(path.to_path_buf()),
+            mode: OpenType::Write,
+            action: Action::Allow,
+            source: Source::Answer,
+            comment: None,
+        };
+        Policy::default().learn(rule, Some(&file)).unwrap();
+        let mut p = Policy::default();
+        let loaded = p.include(&file);
+        let _ = fs::remove_file(&file);
+
+        loaded.unwrap();
+        assert_eq!(p.rules.len(), 1);
+        assert_eq!(p.lookup(path, &OpenType::Write).action, &Action::Allow);
+        assert!(p
+            .lookup(Path::new("/noop/a"), &OpenType::Write)
+            .rule
+            .is_none());
+    }
+
+    /// Test that generated contents and transforms only apply to reads
+    #[test]
+    fn transform() {
+        let p = policy(&["/noop/a=@gunzip:/noop/a.gz", "/noop/b=@text:b"]);
+        let gunzip = Action::Virtual(Content::Gunzip(PathBuf::from("/noop/a.gz")));
+        assert_eq!(action(&p, "/noop/a", OpenType::Read), gunzip);
+        assert_eq!(action(&p, "/noop/a", OpenType::Write), Action::Allow);
+        assert_eq!(action(&p, "/noop/b", OpenType::Write), Action::Allow);
+        assert_eq!(
+            action(&p, "/noop/a", OpenType::All),
+            Action::Block(Errno::EROFS)
+        );
+        assert_eq!(p.rules[1].mode, OpenType::Read);
+        assert!(Rule::parse("/noop/a:w=@subst:/noop/a.in", Source::Arg(0)).is_err());
+        assert!(Rule::parse("/noop/b:rw=@text:b", Source::Arg(0)).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+//! Generation of allowlist policies from recorded accesses
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::policy::{exact, is_special};
+use crate::trace::Access;
+use crate::types::OpenType;
+
+/// Number of covered siblings at which rules collapse into a glob or tree
+const SIBLINGS: usize = 3;
+
+/// Directory tree of the paths opened in one mode
+#[derive(Debug, Default)]
+struct Node {
+    /// Set if the path itself was opened
+    opened: bool,
+    children: BTreeMap<OsString, Node>,
+}
+
+impl Node {
+    fn insert(&mut self, path: &Path) {
+        let mut node = self;
+        for part in path.components() {
+            if let Component::Normal(name) = part {
+                node = node.children.entry(name.to_os_string()).or_default();
+            }
+        }
+        node.opened = true;
+    }
+}
+
+/// How much of a directory the rules made for it cover
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cover {
+    /// Only the opened paths
+    Exact,
+    /// Everything opened below the directory, by globs and trees
+    Contents,
+    /// The directory itself and everything below it
+    Tree,
+}
+
+/// Add patterns for everything opened below `dir` to `out`
+///
+/// Directories with enough opened files get a glob. Directories that hold
+/// nothing but enough opened files and subdirectories that are trees
+/// themselves become a tree.
+fn collapse(node: &Node, dir: &Path, out: &mut Vec<String>) -> Cover {
+    let plain = dir.parent().is_some() && !is_special(dir);
+    let mut files = Vec::new();
+    let mut trees = Vec::new();
+    let mut rules = Vec::new();
+    let mut exact = false;
+    for (name, child) in &node.children {
+        let path = dir.join(name);
+        if child.children.is_empty() {
+            files.push(path);
+            continue;
+        }
+
+        match collapse(child, &path, &mut rules) {
+            Cover::Tree => trees.push(path),
+            cover => {
+                exact |= cover == Cover::Exact;
+                if child.opened {
+                    rules.push(pattern(&path));
+                }
+            }
+        }
+    }
+
+    let covered = files.len() + trees.len();
+    if plain && covered >= SIBLINGS && complete(dir, &files, &trees) {
+        out.push(format!("{}/", home(dir)));
+        return Cover::Tree;
+    }
+    out.append(&mut rules);
+
+    if plain && files.len() >= SIBLINGS {
+        out.push(glob(dir, &files));
+    } else if !files.is_empty() {
+        out.extend(files.iter().map(|file| pattern(file)));
+        exact = true;
+    }
+    if exact {
+        Cover::Exact
+    } else {
+        Cover::Contents
+    }
+}
+
+/// Checks if every entry now in `dir` is one of `files` or `trees`
+fn complete(dir: &Path, files: &[PathBuf], trees: &[PathBuf]) -> bool {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.into_iter().all(|entry| match entry {
+        Ok(entry) => files.iter().chain(trees).any(|path| *path == entry.path()),
+        Err(_) => false,
+    })
+}
+
+/// Pattern matching exactly `path`, relative to `~` if it is plain
+fn pattern(path: &Path) -> String {
+    if is_special(path) {
+        exact(path)
+    } else {
+        home(path)
+    }
+}
+
+/// Glob matching `files` in `dir`, narrowed to their extension if shared
+fn glob(dir: &Path, files: &[PathBuf]) -> String {
+    let ext = files[0].extension().filter(|ext| {
+        !is_special(Path::new(ext)) && files.iter().all(|file| file.extension() == Some(ext))
+    });
+    match ext {
+        Some(ext) => format!("{}/*.{}", home(dir), ext.to_string_lossy()),
+        None => format!("{}/*", home(dir)),
+    }
+}
+
+/// Write `path` relative to `~` if it is in the home directory
+fn home(path: &Path) -> String {
+    if let Some(home) = env::var_os("HOME") {
+        if let Ok(rest) = path.strip_prefix(&home) {
+            if home != "/" {
+                return Path::new("~").join(rest).to_string_lossy().into_owned();
+            }
+        }
+    }
+    path.to_string_lossy().into_owned()
+}
+
+/// Glob for paths in the `/proc` directory of some process, which differs
+/// between runs
+fn proc_glob(path: &Path) -> Option<String> {
+    let rest = path.strip_prefix("/proc").ok()?;
+    let mut parts = rest.components();
+    let pid = parts.next()?.as_os_str().to_string_lossy();
+    if !pid.chars().all(|c| c.is_ascii_digit()) || is_special(parts.as_path()) {
+        return None;
+    }
+    Some(format!("/proc/*/{}", parts.as_path().display()))
+}
+
+/// Minimal policy allowing the `accesses` recorded from `from`
+///
+/// Everything else is blocked. Paths only read, only written and both read
+/// and written each get their own section.
+pub fn generate(accesses: &[Access], from: &str) -> String {
+    let mut modes: BTreeMap<&Path, (bool, bool)> = BTreeMap::new();
+    for access in accesses {
+        let (read, write) = modes.entry(&access.path).or_default();
+        *read |= access.mode != OpenType::Write;
+        *write |= access.mode != OpenType::Read;
+    }
+
+    let mut text = format!(
+        "# Recorded by `noop record` from: {}\ndefault rw block\n",
+        from
+    );
+    let sections = [
+        ((true, false), ":r", "Only read"),
+        ((false, true), ":w", "Only written"),
+        ((true, true), "", "Read and written"),
+    ];
+    for (access, suffix, title) in sections.iter() {
+        let mut root = Node::default();
+        let mut patterns = Vec::new();
+        for (path, _) in modes.iter().filter(|(_, mode)| *mode == access) {
+            match proc_glob(path) {
+                Some(glob) if !patterns.contains(&glob) => patterns.push(glob),
+                Some(_) => (),
+                None => root.insert(path),
+            }
+        }
+        collapse(&root, Path::new("/"), &mut patterns);
+        if root.opened {
+            patterns.push(String::from("/"));
+        }
+        if patterns.is_empty() {
+            continue;
+        }
+
+        text.push_str(&format!("\n# {}\n", title));
+        for pattern in patterns {
+            text.push_str(&format!("{}{}:allow\n", pattern, suffix));
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Build accesses opening each of `paths` in `mode`
+    fn accesses(paths: &[&str], mode: OpenType) -> Vec<Access> {
+        paths
+            .iter()
+            .map(|path| Access {
+                pid: 1,
+                exe: String::from("test"),
+                syscall: String::from("openat"),
+                path: PathBuf::from(path),
+                mode: mode.clone(),
+                flags: None,
+                result: None,
+            })
+            .collect()
+    }
+
+    /// Returns the rules of a generated policy
+    fn rules(accesses: &[Access]) -> Vec<String> {
+        generate(accesses, "test")
+            .lines()
+            .skip(2)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect()
+    }
+
+    /// Test that modes get separate rules
+    #[test]
+    fn modes() {
+        let mut a = accesses(&["/noop/r", "/noop/rw"], OpenType::Read);
+        a.extend(accesses(&["/noop/w", "/noop/rw"], OpenType::Write));
+        assert_eq!(
+            rules(&a),
+            vec!["/noop/r:r:allow", "/noop/w:w:allow", "/noop/rw:allow"]
+        );
+    }
+
+    /// Test collapsing siblings into globs and trees
+    #[test]
+    fn collapse() {
+        let a = accesses(
+            &[
+                "/noop/a/1.so",
+                "/noop/a/2.so",
+                "/noop/a/3.so",
+                "/noop/b/x",
+                "/noop/b/y",
+                "/noop/b/z.txt",
+                "/noop/c/1",
+                "/noop/c/2",
+            ],
+            OpenType::Read,
+        );
+        assert_eq!(
+            rules(&a),
+            vec![
+                "/noop/a/*.so:r:allow",
+                "/noop/b/*:r:allow",
+                "/noop/c/1:r:allow",
+                "/noop/c/2:r:allow"
+            ]
+        );
+
+        let a = accesses(
+            &[
+                "/noop/a/1",
+                "/noop/a/2",
+                "/noop/a/3",
+                "/noop/b/1",
+                "/noop/b/2",
+                "/noop/b/3",
+                "/noop/c/d/1",
+                "/noop/c/d/2",
+                "/noop/c/d/3",
+            ],
+            OpenType::Read,
+        );
+        assert_eq!(
+            rules(&a),
+            vec![
+                "/noop/a/*:r:allow",
+                "/noop/b/*:r:allow",
+                "/noop/c/d/*:r:allow"
+            ]
+        );
+    }
+
+    /// Test that only directories opened in full become trees
+    #[test]
+    fn tree() {
+        let dir = env::temp_dir().join(format!("noop-record-{}", std::process::id()));
+        let mut paths = Vec::new();
+        for sub in &["a", "b", "c"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+            for file in &["1", "2", "3"] {
+                let path = dir.join(sub).join(file);
+                fs::write(&path, "").unwrap();
+                paths.push(path.to_string_lossy().into_owned());
+            }
+        }
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        let a = accesses(&paths, OpenType::Read);
+        let whole = rules(&a);
+        fs::write(dir.join("a").join("4"), "").unwrap();
+        let partial = rules(&a);
+        let _ = fs::remove_dir_all(&dir);
+
+        let dir = dir.display();
+        assert_eq!(whole, vec![format!("{}/:r:allow", dir)]);
+        assert_eq!(
+            partial,
+            vec![
+                format!("{}/a/*:r:allow", dir),
+                format!("{}/b/:r:allow", dir),
+                format!("{}/c/:r:allow", dir),
+            ]
+        );
+    }
+
+    /// Test writing paths that are not plain rules
+    #[test]
+    fn special() {
+        let a = accesses(&["/noop/a b=c.d", "/proc/42/maps"], OpenType::Read);
+        assert_eq!(
+            rules(&a),
+            vec![
+                "/proc/*/maps:r:allow",
+                "re:^/noop/a\\x20b\\x3dc\\x2ed$:r:allow"
+            ]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Path resolution shared by argument parsing and interception
+
+extern crate nix;
+use nix::libc::AT_FDCWD;
+use nix::sys::statfs::{statfs, PROC_SUPER_MAGIC};
+use nix::unistd::Pid;
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Canonicalize `path`, resolving as much of it as exists
+///
+/// Trailing components that do not exist yet are appended unchanged, so a
+/// file that is about to be created still resolves to a stable path.
+pub fn canonicalize(path: &Path) -> PathBuf {
+    if let Ok(full_path) = fs::canonicalize(path) {
+        return full_path;
+    }
+
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if parent != Path::new("") => canonicalize(parent).join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Most symlinks followed while resolving a path, as in the kernel
+const MAX_LINKS: usize = 40;
+
+/// Resolve `path` the way the kernel would when opened by `pid`
+///
+/// Absolute paths are looked up under the tracee's root, relative ones under
+/// `dirfd` if given or the tracee's working directory otherwise. This takes
+/// chroots, `cd` and `openat` directory descriptors into account.
+pub fn in_tracee(pid: Pid, dirfd: Option<i32>, path: &str) -> PathBuf {
+    walk(pid, dirfd, path, true)
+}
+
+/// Resolve `path` like `in_tracee` without following a final symlink
+///
+/// Calls such as `unlink` and `rename` act on the link rather than its target.
+pub fn entry_in_tracee(pid: Pid, dirfd: Option<i32>, path: &str) -> PathBuf {
+    walk(pid, dirfd, path, false)
+}
+
+/// Resolve `path` for `pid` one component at a time
+///
+/// Symlinks are followed against the tracee's root rather than noop's, and
+/// `/proc/self` and `/proc/thread-self` name the tracee. Other symlinks in
+/// `/proc`, such as `cwd` and `fd/N`, already point at a path as seen from
+/// noop. Once a component does not exist the rest is appended unchanged.
+fn walk(pid: Pid, dirfd: Option<i32>, path: &str, follow: bool) -> PathBuf {
+    let proc = PathBuf::from(format!("/proc/{}", pid));
+    let root = fs::read_link(proc.join("root")).unwrap_or_else(|_| PathBuf::from("/"));
+    let mut resolved = if Path::new(path).is_absolute() {
+        root.clone()
+    } else {
+        let base = match dirfd {
+            Some(fd) if fd != AT_FDCWD => proc.join("fd").join(fd.to_string()),
+            _ => proc.join("cwd"),
+        };
+        fs::read_link(base).unwrap_or_else(|_| root.clone())
+    };
+
+    let mut pending: Vec<OsString> = components(Path::new(path));
+    let mut links = 0;
+    let mut missing = false;
+    while let Some(name) = pending.pop() {
+        if name == ".." {
+            if resolved != root {
+                resolved.pop();
+            }
+            continue;
+        }
+
+        let entry = resolved.join(&name);
+        let last = pending.is_empty();
+        let link = match fs::symlink_metadata(&entry) {
+            Ok(meta) => meta.file_type().is_symlink(),
+            Err(_) => {
+                missing = true;
+                false
+            }
+        };
+        if missing || !link || (last && !follow) || links == MAX_LINKS {
+            resolved = entry;
+            continue;
+        }
+
+        links += 1;
+        let in_proc = match statfs(&resolved) {
+            Ok(fs) => fs.filesystem_type() == PROC_SUPER_MAGIC,
+            Err(_) => false,
+        };
+        let target = if in_proc && name == "self" {
+            PathBuf::from(tgid(pid))
+        } else if in_proc && name == "thread-self" {
+            PathBuf::from(format!("{}/task/{}", tgid(pid), pid))
+        } else {
+            match fs::read_link(&entry) {
+                Ok(target) => target,
+                Err(_) => {
+                    resolved = entry;
+                    continue;
+                }
+            }
+        };
+
+        if in_proc && target.is_absolute() {
+            resolved = target;
+        } else {
+            if target.is_absolute() {
+                resolved = root.clone();
+            }
+            pending.extend(components(&target));
+        }
+    }
+
+    resolved
+}
+
+/// Names in `path` in reverse, skipping the root and `.`
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Process id of the thread group `pid` is in, `pid` if it cannot be read
+fn tgid(pid: Pid) -> String {
+    fs::read_to_string(format!("/proc/{}/status", pid))
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find(|line| line.starts_with("Tgid:"))
+                .map(|line| line["Tgid:".len()..].trim().to_string())
+        })
+        .unwrap_or_else(|| pid.to_string())
+}
+
+/// Name of the program `pid` is running, `?` if it cannot be read
+pub fn exe(pid: Pid) -> String {
+    fs::read_link(format!("/proc/{}/exe", pid))
+        .ok()
+        .and_then(|exe| {
+            exe.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| String::from("?"))
+}
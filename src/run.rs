@@ -0,0 +1,66 @@
+//! Identity of a single run of noop
+
+use std::path::PathBuf;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::err::Result;
+use crate::expand::expand;
+
+/// Identifier of this run, `YYYYMMDD-HHMMSS-PID` in UTC
+///
+/// Sorts by start time and stays unique across concurrent runs.
+pub fn id() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = date(secs / 86400);
+    let time = secs % 86400;
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        process::id()
+    )
+}
+
+/// Directory holding the state of run `id` between invocations of noop
+pub fn dir(id: &str) -> Result<PathBuf> {
+    let runs = expand("${XDG_DATA_HOME}/noop/runs")?;
+    Ok(PathBuf::from(runs).join(id))
+}
+
+/// Civil date of the day `days` after 1970-01-01
+fn date(days: u64) -> (u64, u64, u64) {
+    // Count from 0000-03-01 so leap days end each 400 year era
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let doe = days % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + yoe + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test conversion of days since the epoch to dates
+    #[test]
+    fn dates() {
+        assert_eq!(date(0), (1970, 1, 1));
+        assert_eq!(date(11016), (2000, 2, 29));
+        assert_eq!(date(20744), (2026, 10, 18));
+    }
+}
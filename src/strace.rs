@@ -0,0 +1,508 @@
+//! Import of `strace` text logs as access traces
+
+extern crate nix;
+use nix::errno::Errno;
+use nix::libc::{
+    O_APPEND, O_CLOEXEC, O_CREAT, O_DIRECTORY, O_EXCL, O_NOCTTY, O_NOFOLLOW, O_NONBLOCK, O_PATH,
+    O_RDONLY, O_RDWR, O_SYNC, O_TMPFILE, O_TRUNC, O_WRONLY,
+};
+
+use std::collections::HashMap;
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+use crate::intercept::Syscall;
+use crate::trace::Access;
+use crate::types::OpenType;
+
+/// End of a line whose call is completed on a later line
+static UNFINISHED: &str = " <unfinished ...>";
+
+/// Names of the `open` flags strace prints
+static FLAGS: &[(&str, i32)] = &[
+    ("O_RDONLY", O_RDONLY),
+    ("O_WRONLY", O_WRONLY),
+    ("O_RDWR", O_RDWR),
+    ("O_CREAT", O_CREAT),
+    ("O_EXCL", O_EXCL),
+    ("O_NOCTTY", O_NOCTTY),
+    ("O_TRUNC", O_TRUNC),
+    ("O_APPEND", O_APPEND),
+    ("O_NONBLOCK", O_NONBLOCK),
+    ("O_SYNC", O_SYNC),
+    ("O_DIRECTORY", O_DIRECTORY),
+    ("O_NOFOLLOW", O_NOFOLLOW),
+    ("O_CLOEXEC", O_CLOEXEC),
+    ("O_PATH", O_PATH),
+    ("O_TMPFILE", O_TMPFILE),
+];
+
+/// Positions of the arguments of a call, as strace prints them
+struct Layout {
+    /// Directory descriptor the path is relative to, if any
+    dirfd: Option<usize>,
+    path: usize,
+    /// `open` flags, if any
+    flags: Option<usize>,
+    /// Directory descriptor and path of the destination of a rename
+    new_path: Option<(Option<usize>, usize)>,
+}
+
+impl Layout {
+    fn of(sys: Syscall) -> Layout {
+        use crate::intercept::Syscall::*;
+        let (dirfd, path) = match sys {
+            Open | Creat | Unlink | Rename | Mkdir | Rmdir | Truncate | Mknod | Chmod | Chown
+            | Lchown => (None, 0),
+            OpenAt | UnlinkAt | RenameAt | RenameAt2 | MkdirAt | MknodAt | FchmodAt | FchownAt => {
+                (Some(0), 1)
+            }
+            // Only the new name of a link is written
+            Symlink | Link => (None, 1),
+            SymlinkAt => (Some(1), 2),
+            LinkAt => (Some(2), 3),
+            // Descriptors are not paths, so these calls are skipped
+            Fchmod | Fchown => (None, 0),
+        };
+        let flags = match sys {
+            Open | OpenAt => Some(path + 1),
+            _ => None,
+        };
+        let new_path = match sys {
+            Rename => Some((None, 1)),
+            RenameAt | RenameAt2 => Some((Some(2), 3)),
+            _ => None,
+        };
+
+        Layout {
+            dirfd,
+            path,
+            flags,
+            new_path,
+        }
+    }
+}
+
+/// Call parsed from a line of the log
+#[derive(Debug, PartialEq)]
+struct Call {
+    name: String,
+    args: Vec<String>,
+    result: Option<i64>,
+}
+
+/// Split the process id off a line, 0 if strace ran without `-f`
+fn pid(line: &str) -> (i32, &str) {
+    let line = line.trim_start();
+    let (pid, rest) = if line.starts_with("[pid") {
+        let rest = &line[4..];
+        match rest.find(']') {
+            Some(end) => (rest[..end].trim(), &rest[end + 1..]),
+            None => return (0, line),
+        }
+    } else {
+        let end = line.find(' ').unwrap_or(0);
+        (&line[..end], &line[end..])
+    };
+
+    match pid.parse() {
+        Ok(pid) => (pid, rest.trim_start()),
+        Err(_) => (0, line),
+    }
+}
+
+/// Drop timestamps printed by `-t`, `-tt`, `-ttt` or `-r`
+fn skip_times(mut line: &str) -> &str {
+    while line.starts_with(|c: char| c.is_ascii_digit()) {
+        match line.find(' ') {
+            Some(end) => line = line[end..].trim_start(),
+            None => break,
+        }
+    }
+    line
+}
+
+/// Split the arguments of a call at top level commas
+///
+/// Quoted strings are unescaped and keep their quotes, so they can be told
+/// from other arguments.
+fn arguments(text: &str) -> Vec<String> {
+    let mut args = vec![String::new()];
+    let mut depth = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let arg = args.last_mut().unwrap();
+        match c {
+            '"' => {
+                arg.push('"');
+                arg.push_str(&unquote(&mut chars));
+                arg.push('"');
+            }
+            '[' | '{' | '(' => {
+                depth += 1;
+                arg.push(c);
+            }
+            ']' | '}' | ')' => {
+                depth -= 1;
+                arg.push(c);
+            }
+            ',' if depth == 0 => args.push(String::new()),
+            c => arg.push(c),
+        }
+    }
+
+    args.into_iter().map(|arg| arg.trim().to_string()).collect()
+}
+
+/// Read the rest of a quoted string up to its closing quote, unescaping it
+///
+/// Bytes that are not valid UTF-8 are replaced.
+fn unquote(chars: &mut std::str::Chars) -> String {
+    let mut bytes = Vec::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => bytes.push(b'\n'),
+                Some('t') => bytes.push(b'\t'),
+                Some('r') => bytes.push(b'\r'),
+                Some('v') => bytes.push(0x0b),
+                Some('f') => bytes.push(0x0c),
+                Some('x') => {
+                    let hex: String = chars.clone().take(2).collect();
+                    if let Ok(byte) = u8::from_str_radix(&hex, 16) {
+                        bytes.push(byte);
+                        chars.nth(1);
+                    }
+                }
+                Some(c) if c.is_digit(8) => {
+                    let rest: String = chars
+                        .clone()
+                        .take_while(|c| c.is_digit(8))
+                        .take(2)
+                        .collect();
+                    let octal = format!("{}{}", c, rest);
+                    bytes.push(u8::from_str_radix(&octal, 8).unwrap_or(0));
+                    for _ in 0..rest.len() {
+                        chars.next();
+                    }
+                }
+                Some(c) => bytes.extend(c.to_string().as_bytes()),
+                None => (),
+            },
+            c => bytes.extend(c.to_string().as_bytes()),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Parse the return value of a call, such as `3` or `-1 ENOENT (...)`
+///
+/// Failures give the negated errno, as the kernel returns them.
+fn result(text: &str) -> Option<i64> {
+    let mut parts = text.split_whitespace();
+    let value = parts.next()?;
+    let digits = value.find(|c: char| !(c.is_ascii_digit() || c == '-'));
+    let value: i64 = value[..digits.unwrap_or(value.len())].parse().ok()?;
+    if value != -1 {
+        return Some(value);
+    }
+
+    let name = parts.next()?;
+    (1..256)
+        .map(Errno::from_i32)
+        .find(|errno| format!("{:?}", errno) == name)
+        .map(|errno| -(errno as i64))
+        .or(Some(-1))
+}
+
+/// Parse a complete call such as `openat(AT_FDCWD, "/etc", O_RDONLY) = 3`
+fn call(text: &str) -> Option<Call> {
+    let open = text.find('(')?;
+    let close = text.rfind(") = ")?;
+    if close < open {
+        return None;
+    }
+
+    Some(Call {
+        name: text[..open].to_string(),
+        args: arguments(&text[open + 1..close]),
+        result: result(&text[close + ") = ".len()..]),
+    })
+}
+
+/// Parse `open` flags written as `O_WRONLY|O_CREAT` or as a number
+fn flags(text: &str) -> i32 {
+    text.split('|')
+        .map(|flag| {
+            if flag.starts_with("0x") {
+                return i32::from_str_radix(&flag[2..], 16).unwrap_or(0);
+            }
+            FLAGS
+                .iter()
+                .find(|(name, _)| *name == flag)
+                .map(|(_, value)| *value)
+                .or_else(|| flag.parse().ok())
+                .unwrap_or(0)
+        })
+        .fold(0, |all, flag| all | flag)
+}
+
+/// Quoted string argument as a path
+fn path_arg(arg: &str) -> Option<PathBuf> {
+    if arg.len() < 2 || !arg.starts_with('"') || !arg.ends_with('"') {
+        return None;
+    }
+    Some(PathBuf::from(&arg[1..arg.len() - 1]))
+}
+
+/// Path of a descriptor annotated by `strace -y`, as in `3</etc>`
+fn fd_path(fd: &str) -> Option<PathBuf> {
+    match (fd.find('<'), fd.ends_with('>')) {
+        (Some(start), true) => Some(PathBuf::from(&fd[start + 1..fd.len() - 1])),
+        _ => None,
+    }
+}
+
+/// Directory a relative path is resolved from
+///
+/// Descriptors annotated by `strace -y` give their directory, others the
+/// current directory.
+fn base(dirfd: Option<&String>, cwd: &Path) -> PathBuf {
+    dirfd
+        .and_then(|fd| fd_path(fd))
+        .unwrap_or_else(|| cwd.to_path_buf())
+}
+
+/// Absolute version of `path`, with `.` and `..` removed without touching the
+/// file system, which may not be the one the log was taken on
+fn absolute(base: &Path, path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for part in base.join(path).components() {
+        match part {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                out.pop();
+            }
+            part => out.push(part),
+        }
+    }
+    out
+}
+
+/// Parse the log of `strace -f -e trace=file` into accesses
+///
+/// Calls noop handles become accesses, a rename one for each of its paths.
+/// Other lines are skipped, apart from successful `execve` calls, which name
+/// the program of later calls from the same process, and successful `chdir`
+/// and `fchdir` calls, which set its working directory. Children start in the
+/// directory of their parent if the log has process calls, as with `strace
+/// -e trace=file,process`. Relative paths are taken from the working
+/// directory of their process, or the current one if it is unknown, unless
+/// `strace -y` showed their directory.
+pub fn import(log: &str) -> Vec<Access> {
+    let start = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+    let mut cwds: HashMap<i32, PathBuf> = HashMap::new();
+    let mut exes: HashMap<i32, String> = HashMap::new();
+    let mut unfinished: HashMap<i32, String> = HashMap::new();
+    let mut accesses = Vec::new();
+
+    for line in log.lines() {
+        let (pid, rest) = pid(line);
+        let rest = skip_times(rest);
+
+        // Calls interrupted by other processes are split over two lines
+        let text = if rest.ends_with(UNFINISHED) {
+            let start = &rest[..rest.len() - UNFINISHED.len()];
+            unfinished.insert(pid, start.to_string());
+            continue;
+        } else if rest.starts_with("<... ") {
+            let end = match rest.find(" resumed>") {
+                Some(end) => end + " resumed>".len(),
+                None => continue,
+            };
+            match unfinished.remove(&pid) {
+                Some(start) => format!("{}{}", start, &rest[end..]),
+                None => continue,
+            }
+        } else {
+            rest.to_string()
+        };
+        let call = match call(&text) {
+            Some(call) => call,
+            None => continue,
+        };
+
+        let cwd = cwds.get(&pid).unwrap_or(&start).clone();
+        let first = call.args.first();
+        match (call.name.as_str(), call.result) {
+            ("chdir", Some(0)) => {
+                if let Some(dir) = first.and_then(|arg| path_arg(arg)) {
+                    cwds.insert(pid, absolute(&cwd, &dir));
+                }
+                continue;
+            }
+            ("fchdir", Some(0)) => {
+                if let Some(dir) = first.and_then(|arg| fd_path(arg)) {
+                    cwds.insert(pid, dir);
+                }
+                continue;
+            }
+            ("clone", Some(child))
+            | ("clone3", Some(child))
+            | ("fork", Some(child))
+            | ("vfork", Some(child))
+                if child > 0 =>
+            {
+                cwds.insert(child as i32, cwd);
+                continue;
+            }
+            _ => (),
+        }
+
+        if call.name == "execve" && call.result == Some(0) {
+            let exe = call.args.first().and_then(|arg| path_arg(arg));
+            if let Some(name) = exe.as_ref().and_then(|exe| exe.file_name()) {
+                exes.insert(pid, name.to_string_lossy().into_owned());
+            }
+            continue;
+        }
+        let sys = match Syscall::parse(&call.name) {
+            Some(sys) => sys,
+            None => continue,
+        };
+
+        let layout = Layout::of(sys);
+        let flags = match (sys, layout.flags) {
+            (Syscall::Creat, _) => Some(O_CREAT | O_WRONLY | O_TRUNC),
+            (_, Some(i)) => Some(call.args.get(i).map_or(0, |arg| flags(arg))),
+            (_, None) => None,
+        };
+        let mode = flags.map_or(OpenType::Write, OpenType::from);
+
+        let mut paths = vec![(layout.dirfd, layout.path)];
+        paths.extend(layout.new_path);
+        for (dirfd, path) in paths {
+            let path = match call.args.get(path).and_then(|arg| path_arg(arg)) {
+                Some(path) => path,
+                None => continue,
+            };
+            let base = base(dirfd.and_then(|i| call.args.get(i)), &cwd);
+            accesses.push(Access {
+                pid,
+                exe: exes.get(&pid).cloned().unwrap_or_else(|| String::from("?")),
+                syscall: call.name.clone(),
+                path: absolute(&base, &path),
+                mode: mode.clone(),
+                flags,
+                result: call.result,
+            });
+        }
+    }
+
+    accesses
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test importing calls of several processes
+    #[test]
+    fn import() {
+        let log = r#"
+4200  execve("/usr/bin/cat", ["cat", "a"], 0x7ffc /* 20 vars */) = 0
+4200  openat(AT_FDCWD, "/etc/ld.so.cache", O_RDONLY|O_CLOEXEC) = 3
+[pid  4201] 12:00:00.000001 creat("/noop/out", 0644) = -1 EACCES (Permission denied)
+4200  openat(3</noop/dir>, "../a\x20b", O_RDWR <unfinished ...>
+4201  rename("/noop/old", "/noop/new") = 0
+4200  <... openat resumed>) = 4</noop/a b>
+4200  stat("/noop", {st_mode=S_IFDIR|0755, ...}) = 0
+4200  +++ exited with 0 +++
+"#;
+        let summary: Vec<_> = super::import(log)
+            .into_iter()
+            .map(|a| (a.pid, a.exe, a.syscall, a.path, a.mode, a.flags, a.result))
+            .collect();
+
+        let path = PathBuf::from;
+        let cat = || String::from("cat");
+        let unknown = || String::from("?");
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    4200,
+                    cat(),
+                    String::from("openat"),
+                    path("/etc/ld.so.cache"),
+                    OpenType::Read,
+                    Some(O_CLOEXEC),
+                    Some(3)
+                ),
+                (
+                    4201,
+                    unknown(),
+                    String::from("creat"),
+                    path("/noop/out"),
+                    OpenType::Write,
+                    Some(O_CREAT | O_WRONLY | O_TRUNC),
+                    Some(-(Errno::EACCES as i64))
+                ),
+                (
+                    4201,
+                    unknown(),
+                    String::from("rename"),
+                    path("/noop/old"),
+                    OpenType::Write,
+                    None,
+                    Some(0)
+                ),
+                (
+                    4201,
+                    unknown(),
+                    String::from("rename"),
+                    path("/noop/new"),
+                    OpenType::Write,
+                    None,
+                    Some(0)
+                ),
+                (
+                    4200,
+                    cat(),
+                    String::from("openat"),
+                    path("/noop/a b"),
+                    OpenType::All,
+                    Some(O_RDWR),
+                    Some(4)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn chdir() {
+        let log = r#"
+4300  chdir("/noop/build") = 0
+4300  chdir("missing") = -1 ENOENT (No such file or directory)
+4300  clone(child_stack=NULL, flags=SIGCHLD) = 4301
+4300  chdir("sub") = 0
+4300  openat(AT_FDCWD, "x.c", O_RDONLY) = 3
+4301  openat(AT_FDCWD, "../y.c", O_RDONLY) = 3
+4301  fchdir(3</noop/lib>) = 0
+4301  openat(AT_FDCWD, "z.c", O_RDONLY) = 4
+"#;
+        let paths: Vec<_> = super::import(log)
+            .into_iter()
+            .map(|a| (a.pid, a.path))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                (4300, PathBuf::from("/noop/build/sub/x.c")),
+                (4301, PathBuf::from("/noop/y.c")),
+                (4301, PathBuf::from("/noop/lib/z.c")),
+            ]
+        );
+    }
+}
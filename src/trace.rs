@@ -0,0 +1,103 @@
+//! Accesses observed while tracing a program, saved as JSON lines
+
+use serde_json::{json, Value};
+
+use std::path::PathBuf;
+
+use crate::err::{Error, Result};
+use crate::types::OpenType;
+
+/// Single `open` or change of the file tree made by a traced process
+#[derive(Debug, Clone, PartialEq)]
+pub struct Access {
+    pub pid: i32,
+    /// Name of the program that made the call
+    pub exe: String,
+    /// Name of the syscall, such as `openat`
+    pub syscall: String,
+    /// Resolved absolute path
+    pub path: PathBuf,
+    pub mode: OpenType,
+    /// Flags passed to `open`, `None` for other calls
+    pub flags: Option<i32>,
+    /// Value the call returned, a negated errno on failure, `None` if unknown
+    pub result: Option<i64>,
+}
+
+impl Access {
+    /// Line of a trace file holding the access
+    ///
+    /// The mode is written like a rule suffix, as `r`, `w` or `rw`.
+    pub fn to_json(&self) -> String {
+        json!({
+            "pid": self.pid,
+            "exe": self.exe,
+            "syscall": self.syscall,
+            "path": self.path.to_string_lossy(),
+            "mode": self.mode.to_string().to_lowercase(),
+            "flags": self.flags,
+            "result": self.result,
+        })
+        .to_string()
+    }
+
+    /// Parse access from a line of a trace file
+    ///
+    /// Flags and result are optional.
+    pub fn parse(line: &str) -> Result<Access> {
+        let value: Value = serde_json::from_str(line)?;
+        let field = |name: &str| value.get(name).and_then(Value::as_str);
+        let malformed = Error::String {
+            reason: "Malformed access in trace",
+        };
+
+        let pid = value.get("pid").and_then(Value::as_i64);
+        let mode = field("mode").and_then(OpenType::parse);
+        match (pid, field("exe"), field("syscall"), field("path"), mode) {
+            (Some(pid), Some(exe), Some(syscall), Some(path), Some(mode)) => Ok(Access {
+                pid: pid as i32,
+                exe: exe.to_string(),
+                syscall: syscall.to_string(),
+                path: PathBuf::from(path),
+                mode,
+                flags: value.get("flags").and_then(Value::as_i64).map(|f| f as i32),
+                result: value.get("result").and_then(Value::as_i64),
+            }),
+            _ => Err(malformed),
+        }
+    }
+}
+
+/// Parse the accesses of a trace file, skipping blank lines
+pub fn parse(text: &str) -> Result<Vec<Access>> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(Access::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test that accesses survive a trip through a trace file
+    #[test]
+    fn round_trip() {
+        let access = Access {
+            pid: 42,
+            exe: String::from("cat"),
+            syscall: String::from("openat"),
+            path: PathBuf::from("/noop/a \"b\""),
+            mode: OpenType::All,
+            flags: Some(2),
+            result: Some(-2),
+        };
+        let line = access.to_json();
+        assert!(line.contains("\"mode\":\"rw\""));
+        assert_eq!(parse(&format!("{}\n\n", line)).unwrap(), vec![access]);
+        assert!(Access::parse("{\"pid\":1}").is_err());
+        let bare = r#"{"pid":1,"exe":"cat","syscall":"open","path":"/noop","mode":"r"}"#;
+        assert_eq!(Access::parse(bare).unwrap().result, None);
+        assert!(Access::parse("not json").is_err());
+    }
+}
@@ -0,0 +1,663 @@
+//! Staging of a run's file changes for review before they reach the real files
+
+extern crate nix;
+use nix::libc::{EEXIST, EISDIR, ENOENT, ENOTDIR, ENOTEMPTY, EPERM, EXDEV};
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::ask::prompt;
+use crate::err::{Error, Result};
+use crate::overlay::Overlay;
+use crate::run;
+use crate::types::OpenType;
+
+/// What to do with the staged changes once the traced program exits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    /// Prompt on the terminal
+    Ask,
+    /// Apply the changes to the real files
+    Commit,
+    /// Throw the changes away
+    Discard,
+    /// Keep the changes staged for a later `noop commit`
+    Keep,
+}
+
+impl Resolution {
+    /// Parse resolution from its `--transaction=` value
+    pub fn parse(token: &str) -> Option<Self> {
+        use self::Resolution::*;
+        match token {
+            "ask" => Some(Ask),
+            "commit" => Some(Commit),
+            "discard" => Some(Discard),
+            "keep" => Some(Keep),
+            _ => None,
+        }
+    }
+}
+
+/// Change a run made to a single path
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Created(PathBuf),
+    Modified(PathBuf),
+    Deleted(PathBuf),
+}
+
+impl Change {
+    /// Real path that is changed
+    pub fn path(&self) -> &Path {
+        match self {
+            Change::Created(path) | Change::Modified(path) | Change::Deleted(path) => path,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Created(path) => write!(f, "created  {}", path.display()),
+            Change::Modified(path) => write!(f, "modified {}", path.display()),
+            Change::Deleted(path) => write!(f, "deleted  {}", path.display()),
+        }
+    }
+}
+
+/// Staging area standing in for the file system during a run
+///
+/// Written files are copied into `staged` as with an overlay. Deleted files
+/// leave an empty marker at the same place under `deleted`, and renames are
+/// staged as a copy followed by a delete. Removed real directories are listed
+/// in `removed`, separated by NUL bytes.
+#[derive(Debug)]
+pub struct Transaction {
+    /// Run directory holding the staging area
+    pub dir: PathBuf,
+    staged: Overlay,
+    deleted: Overlay,
+}
+
+/// Create `io::Error` for the OS error `errno`
+fn errno(errno: i32) -> io::Error {
+    io::Error::from_raw_os_error(errno)
+}
+
+/// Checks if `path` is a directory, not following symlinks
+fn is_dir(path: &Path) -> bool {
+    match fs::symlink_metadata(path) {
+        Ok(meta) => meta.is_dir(),
+        Err(_) => false,
+    }
+}
+
+/// Create the parent directories of `path`
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) => fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+}
+
+impl Transaction {
+    /// Create an empty staging area for run `id`
+    pub fn create(id: &str) -> Result<Transaction> {
+        let transaction = Transaction::at(run::dir(id)?);
+        transaction.init()?;
+        Ok(transaction)
+    }
+
+    /// Open the staging area kept by an earlier run `id`
+    pub fn open(id: &str) -> Result<Transaction> {
+        let transaction = Transaction::at(run::dir(id)?);
+        if id.contains('/') || !transaction.staged.dir.is_dir() {
+            return Err(Error::Arg {
+                reason: "No staged changes for that run id",
+            });
+        }
+        Ok(transaction)
+    }
+
+    /// Staging area kept in run directory `dir`
+    fn at(dir: PathBuf) -> Transaction {
+        Transaction {
+            staged: Overlay::new(dir.join("staged")),
+            deleted: Overlay::new(dir.join("deleted")),
+            dir,
+        }
+    }
+
+    /// Create the directories of an empty staging area
+    fn init(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.staged.dir)?;
+        fs::create_dir_all(&self.deleted.dir)
+    }
+
+    /// Checks if the run deleted `path`
+    fn is_deleted(&self, path: &Path) -> bool {
+        self.deleted.copy_of(path).is_file()
+    }
+
+    /// Real directories the run removed
+    fn removed(&self) -> io::Result<Vec<PathBuf>> {
+        match fs::read(self.dir.join("removed")) {
+            Ok(list) => Ok(list
+                .split(|&b| b == 0)
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| PathBuf::from(OsString::from_vec(dir.to_vec())))
+                .collect()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replace the list of removed real directories
+    fn set_removed(&self, dirs: &[PathBuf]) -> io::Result<()> {
+        let mut list = Vec::new();
+        for dir in dirs {
+            list.extend_from_slice(dir.as_os_str().as_bytes());
+            list.push(0);
+        }
+        fs::write(self.dir.join("removed"), list)
+    }
+
+    /// Checks if `path` is gone with a real directory the run removed
+    fn is_removed(&self, path: &Path) -> bool {
+        self.removed()
+            .unwrap_or_default()
+            .iter()
+            .any(|dir| path.starts_with(dir))
+    }
+
+    /// Checks if the directory `path` would be created in is removed
+    fn parent_removed(&self, path: &Path) -> bool {
+        match path.parent() {
+            Some(dir) => self.is_removed(dir),
+            None => false,
+        }
+    }
+
+    /// Checks if the run removed `path` or the directory it is in
+    fn is_gone(&self, path: &Path) -> bool {
+        self.is_deleted(path) || self.is_removed(path)
+    }
+
+    /// File or directory holding what the run sees at `path`, if anything
+    fn current(&self, path: &Path) -> Option<PathBuf> {
+        let copy = self.staged.copy_of(path);
+        if fs::symlink_metadata(&copy).is_ok() {
+            Some(copy)
+        } else if !self.is_gone(path) && fs::symlink_metadata(path).is_ok() {
+            Some(path.to_path_buf())
+        } else {
+            None
+        }
+    }
+
+    /// Path an `open` of `path` in `mode` uses instead, if any
+    ///
+    /// Works like `Overlay::target`, except that deleted files are gone and
+    /// directories the run created are found in the staging area.
+    pub fn target(&self, path: &Path, mode: &OpenType) -> io::Result<Option<PathBuf>> {
+        if self.is_removed(path) {
+            return Err(errno(ENOENT));
+        }
+        if self.is_deleted(path) {
+            if *mode == OpenType::Read {
+                return Err(errno(ENOENT));
+            }
+            // Start the file over rather than copying the deleted original
+            let copy = self.staged.copy_of(path);
+            create_parent(&copy)?;
+            fs::File::create(&copy)?;
+            fs::remove_file(self.deleted.copy_of(path))?;
+        }
+
+        let copy = self.staged.copy_of(path);
+        if copy.is_dir() && fs::symlink_metadata(path).is_err() {
+            return Ok(Some(copy));
+        }
+        self.staged.target(path, mode)
+    }
+
+    /// Stage the removal of file `path`
+    pub fn unlink(&self, path: &Path) -> io::Result<()> {
+        let copy = self.staged.copy_of(path);
+        let staged = fs::symlink_metadata(&copy).ok();
+        let real = match fs::symlink_metadata(path) {
+            Ok(_) if self.is_gone(path) => None,
+            real => real.ok(),
+        };
+        match staged.as_ref().or(real.as_ref()) {
+            Some(meta) if meta.is_dir() => return Err(errno(EISDIR)),
+            Some(_) => (),
+            None => return Err(errno(ENOENT)),
+        }
+
+        if staged.is_some() {
+            fs::remove_file(&copy)?;
+        }
+        if real.is_some() {
+            let marker = self.deleted.copy_of(path);
+            create_parent(&marker)?;
+            fs::File::create(marker)?;
+        }
+        Ok(())
+    }
+
+    /// Stage the removal of directory `path`, which has to look empty to
+    /// the run
+    ///
+    /// Directories the run created are removed from the staging area, real
+    /// ones are listed as removed once every entry in them is deleted.
+    pub fn rmdir(&self, path: &Path) -> io::Result<()> {
+        match self.current(path).map(fs::symlink_metadata) {
+            Some(Ok(ref meta)) if meta.is_dir() => (),
+            Some(_) => return Err(errno(ENOTDIR)),
+            None => return Err(errno(ENOENT)),
+        }
+
+        let copy = self.staged.copy_of(path);
+        let staged = copy.is_dir();
+        if staged && fs::read_dir(&copy)?.next().is_some() {
+            return Err(errno(ENOTEMPTY));
+        }
+        let real = !self.is_gone(path) && is_dir(path);
+        if real {
+            for entry in fs::read_dir(path)? {
+                if !self.is_gone(&path.join(entry?.file_name())) {
+                    return Err(errno(ENOTEMPTY));
+                }
+            }
+        }
+
+        if staged {
+            fs::remove_dir(&copy)?;
+        }
+        if real {
+            let mut removed = self.removed()?;
+            removed.push(path.to_path_buf());
+            self.set_removed(&removed)?;
+        }
+        Ok(())
+    }
+
+    /// Stage moving file `from` to `to`, failing if `to` exists and
+    /// `noreplace` is set
+    ///
+    /// Directories cannot be moved into the staging area, their renames fail
+    /// with `EXDEV` so callers fall back to copying.
+    pub fn rename(&self, from: &Path, to: &Path, noreplace: bool) -> io::Result<()> {
+        let source = self.current(from).ok_or_else(|| errno(ENOENT))?;
+        if self.parent_removed(to) {
+            return Err(errno(ENOENT));
+        } else if source.is_dir() {
+            return Err(errno(EXDEV));
+        } else if noreplace && self.current(to).is_some() {
+            return Err(errno(EEXIST));
+        } else if from == to {
+            return Ok(());
+        }
+
+        let copy = self.staged.copy_of(to);
+        create_parent(&copy)?;
+        fs::copy(&source, &copy)?;
+        let marker = self.deleted.copy_of(to);
+        if marker.is_file() {
+            fs::remove_file(marker)?;
+        }
+        self.unlink(from)
+    }
+
+    /// Stage the creation of directory `path`
+    ///
+    /// Recreating a removed real directory takes it off the removed list, as
+    /// everything in it stays deleted.
+    pub fn mkdir(&self, path: &Path) -> io::Result<()> {
+        if self.current(path).is_some() {
+            return Err(errno(EEXIST));
+        } else if self.parent_removed(path) {
+            return Err(errno(ENOENT));
+        }
+
+        let mut removed = self.removed()?;
+        if removed.iter().any(|dir| dir == path) {
+            removed.retain(|dir| dir != path);
+            return self.set_removed(&removed);
+        }
+        fs::create_dir_all(self.staged.copy_of(path))
+    }
+
+    /// Staged file or directory standing in for `path` in a change to it
+    ///
+    /// Real regular files are copied into the staging area first. Other real
+    /// files cannot be staged and fail with `EPERM`.
+    fn changed(&self, path: &Path) -> io::Result<PathBuf> {
+        let current = self.current(path).ok_or_else(|| errno(ENOENT))?;
+        if current.starts_with(&self.staged.dir) {
+            Ok(current)
+        } else if current.is_file() {
+            self.staged
+                .target(path, &OpenType::Write)?
+                .ok_or_else(|| errno(EPERM))
+        } else {
+            Err(errno(EPERM))
+        }
+    }
+
+    /// Stage cutting file `path` down or extending it to `len` bytes
+    pub fn truncate(&self, path: &Path, len: u64) -> io::Result<()> {
+        let file = self.changed(path)?;
+        if file.is_dir() {
+            return Err(errno(EISDIR));
+        }
+        fs::OpenOptions::new().write(true).open(file)?.set_len(len)
+    }
+
+    /// Stage setting the permission bits of `path` to `mode`
+    pub fn chmod(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let file = self.changed(path)?;
+        fs::set_permissions(file, fs::Permissions::from_mode(mode & 0o7777))
+    }
+
+    /// Changes staged so far, ordered by path
+    ///
+    /// Files that were opened for writing but left unchanged are skipped.
+    pub fn changes(&self) -> io::Result<Vec<Change>> {
+        let mut changes = Vec::new();
+        let mut dirs = vec![self.staged.dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let copy = entry?.path();
+                let real = self.real(&self.staged, &copy);
+                let real_meta = fs::symlink_metadata(&real);
+                if copy.is_dir() {
+                    if real_meta.is_err() {
+                        changes.push(Change::Created(real));
+                    }
+                    dirs.push(copy);
+                } else if real_meta.is_err() {
+                    changes.push(Change::Created(real));
+                } else if fs::read(&copy)? != fs::read(&real)?
+                    || fs::metadata(&copy)?.permissions() != fs::metadata(&real)?.permissions()
+                {
+                    changes.push(Change::Modified(real));
+                }
+            }
+        }
+
+        let mut dirs = vec![self.deleted.dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let marker = entry?.path();
+                if marker.is_dir() {
+                    dirs.push(marker);
+                } else {
+                    changes.push(Change::Deleted(self.real(&self.deleted, &marker)));
+                }
+            }
+        }
+        changes.extend(self.removed()?.into_iter().map(Change::Deleted));
+
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(changes)
+    }
+
+    /// Real path that `copy` in `layer` stands for
+    fn real(&self, layer: &Overlay, copy: &Path) -> PathBuf {
+        Path::new("/").join(copy.strip_prefix(&layer.dir).unwrap_or(copy))
+    }
+
+    /// Unified diff of `change` for text files, `None` for binary files or
+    /// if `diff` is not available
+    pub fn diff(&self, change: &Change) -> Option<String> {
+        let real = change.path().to_path_buf();
+        let copy = self.staged.copy_of(&real);
+        let (old, new) = match change {
+            Change::Created(_) => (PathBuf::from("/dev/null"), copy),
+            Change::Modified(_) => (real.clone(), copy),
+            Change::Deleted(_) => (real.clone(), PathBuf::from("/dev/null")),
+        };
+        if !is_text(&old) || !is_text(&new) {
+            return None;
+        }
+
+        let label = real.to_string_lossy();
+        let out = Command::new("diff")
+            .arg("-u")
+            .arg("--label")
+            .arg(&*label)
+            .arg("--label")
+            .arg(&*label)
+            .arg(&old)
+            .arg(&new)
+            .output()
+            .ok()?;
+        Some(String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+
+    /// Apply the staged changes to the real files and drop the staging area
+    pub fn commit(&self) -> Result<Vec<Change>> {
+        let changes = self.changes()?;
+        let mut created = Vec::new();
+        let mut dirs = Vec::new();
+        for change in &changes {
+            let real = change.path();
+            let copy = self.staged.copy_of(real);
+            match change {
+                Change::Created(_) if copy.is_dir() => {
+                    fs::create_dir_all(real)?;
+                    created.push((real, fs::metadata(&copy)?.permissions()));
+                }
+                Change::Created(_) | Change::Modified(_) => {
+                    create_parent(real)?;
+                    fs::copy(&copy, real)?;
+                }
+                // Directories go once what was in them is gone
+                Change::Deleted(_) if is_dir(real) => dirs.push(real),
+                Change::Deleted(_) => match fs::remove_file(real) {
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                    res => res?,
+                },
+            }
+        }
+        for dir in dirs.into_iter().rev() {
+            match fs::remove_dir(dir) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                res => res?,
+            }
+        }
+        // Created directories get their mode once nothing goes into them
+        for (dir, permissions) in created.into_iter().rev() {
+            fs::set_permissions(dir, permissions)?;
+        }
+        self.discard()?;
+
+        Ok(changes)
+    }
+
+    /// Drop the staging area without touching the real files
+    pub fn discard(&self) -> Result<()> {
+        Ok(fs::remove_dir_all(&self.dir)?)
+    }
+
+    /// Show the changes of run `id` and resolve them as `resolution` says
+    pub fn review(&self, id: &str, resolution: Resolution) -> Result<()> {
+        let changes = self.changes()?;
+        if changes.is_empty() {
+            eprintln!("\nNo changes staged");
+            return self.discard();
+        }
+
+        eprintln!("\nCHANGES:");
+        for change in &changes {
+            eprintln!("{}", change);
+        }
+        for change in &changes {
+            if let Some(diff) = self.diff(change) {
+                eprint!("\n{}", diff);
+            }
+        }
+
+        let resolution = match resolution {
+            Resolution::Ask => ask(),
+            resolution => resolution,
+        };
+        match resolution {
+            Resolution::Commit => {
+                self.commit()?;
+                eprintln!("Committed {} changes", changes.len());
+            }
+            Resolution::Discard => {
+                self.discard()?;
+                eprintln!("Discarded {} changes", changes.len());
+            }
+            _ => eprintln!("Kept changes, apply them with `noop commit {}`", id),
+        }
+        Ok(())
+    }
+}
+
+/// Checks if `path` looks like text, judging by its first block
+fn is_text(path: &Path) -> bool {
+    let mut head = Vec::new();
+    match fs::File::open(path) {
+        Ok(file) => file.take(8192).read_to_end(&mut head).is_ok() && !head.contains(&0),
+        Err(_) => false,
+    }
+}
+
+/// Ask on the terminal whether to commit the changes
+///
+/// Keeps the changes if there is no terminal to ask on.
+fn ask() -> Resolution {
+    loop {
+        let answer = prompt("Commit changes? [y]es, [n]o, [k]eep for later: ");
+        match answer.as_deref() {
+            Some("y") | Some("yes") => return Resolution::Commit,
+            Some("n") | Some("no") => return Resolution::Discard,
+            Some("k") | Some("keep") | None => return Resolution::Keep,
+            Some(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    /// Test that changes are staged and only applied on commit
+    #[test]
+    fn stage() {
+        let tmp = env::temp_dir().join(format!("noop-transaction-{}", process::id()));
+        let transaction = Transaction::at(tmp.join("run"));
+        transaction.init().unwrap();
+        let (old, new, gone) = (tmp.join("old"), tmp.join("new"), tmp.join("gone"));
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(&old, "old").unwrap();
+        fs::write(&gone, "gone").unwrap();
+
+        let copy = transaction.target(&old, &OpenType::Write).unwrap().unwrap();
+        fs::write(copy, "changed").unwrap();
+        transaction.rename(&old, &new, false).unwrap();
+        transaction.unlink(&gone).unwrap();
+        let read = transaction.target(&gone, &OpenType::Read);
+        let changes = transaction.changes().unwrap();
+        let staged = fs::read_to_string(&old);
+        transaction.commit().unwrap();
+        let committed = (old.exists(), fs::read_to_string(&new), gone.exists());
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(read.unwrap_err().raw_os_error(), Some(ENOENT));
+        assert_eq!(
+            changes,
+            vec![
+                Change::Deleted(gone),
+                Change::Created(new),
+                Change::Deleted(old)
+            ]
+        );
+        assert_eq!(staged.unwrap(), "old");
+        assert_eq!(committed.0, false);
+        assert_eq!(committed.1.unwrap(), "changed");
+        assert_eq!(committed.2, false);
+    }
+
+    /// Test that removing real directories is staged once they look empty
+    #[test]
+    fn rmdir() {
+        let tmp = env::temp_dir().join(format!("noop-rmdir-{}", process::id()));
+        let transaction = Transaction::at(tmp.join("run"));
+        transaction.init().unwrap();
+        let (dir, file, sub) = (tmp.join("dir"), tmp.join("dir/file"), tmp.join("dir/sub"));
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(&file, "file").unwrap();
+
+        let full = transaction.rmdir(&dir);
+        transaction.unlink(&file).unwrap();
+        transaction.rmdir(&sub).unwrap();
+        transaction.rmdir(&dir).unwrap();
+        let (read, made) = (
+            transaction.target(&file, &OpenType::Read),
+            transaction.mkdir(&sub),
+        );
+        let staged = (dir.is_dir(), transaction.changes().unwrap());
+        transaction.commit().unwrap();
+        let committed = dir.exists();
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(full.unwrap_err().raw_os_error(), Some(ENOTEMPTY));
+        assert_eq!(read.unwrap_err().raw_os_error(), Some(ENOENT));
+        assert_eq!(made.unwrap_err().raw_os_error(), Some(ENOENT));
+        assert!(staged.0);
+        assert_eq!(
+            staged.1,
+            vec![
+                Change::Deleted(dir),
+                Change::Deleted(file),
+                Change::Deleted(sub)
+            ]
+        );
+        assert!(!committed);
+    }
+
+    /// Test that truncating and changing modes is staged on copies
+    #[test]
+    fn truncate_chmod() {
+        let tmp = env::temp_dir().join(format!("noop-chmod-{}", process::id()));
+        let transaction = Transaction::at(tmp.join("run"));
+        transaction.init().unwrap();
+        let (file, dir) = (tmp.join("file"), tmp.join("dir"));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&file, "file").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+
+        transaction.truncate(&file, 2).unwrap();
+        transaction.chmod(&file, 0o755).unwrap();
+        let real_dir = transaction.chmod(&dir, 0o700);
+        let staged = (fs::read(&file).unwrap(), transaction.changes().unwrap());
+        transaction.commit().unwrap();
+        let committed = (
+            fs::read(&file).unwrap(),
+            fs::metadata(&file).unwrap().permissions().mode() & 0o777,
+        );
+        let _ = fs::remove_dir_all(&tmp);
+
+        assert_eq!(real_dir.unwrap_err().raw_os_error(), Some(EPERM));
+        assert_eq!(staged.0, b"file");
+        assert_eq!(staged.1, vec![Change::Modified(file)]);
+        assert_eq!(committed, (b"fi".to_vec(), 0o755));
+    }
+}
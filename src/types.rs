@@ -1,10 +1,13 @@
 //! Common types used across the crate, including file blocking data
 
 extern crate nix;
+use nix::errno::Errno;
 use nix::libc::{O_RDWR, O_WRONLY};
 
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use crate::content::Content;
 
 /// `open` mode to block
 #[derive(PartialEq, Debug, Clone)]
@@ -27,6 +30,24 @@ impl fmt::Display for OpenType {
     }
 }
 
+impl OpenType {
+    /// Parse mode from its rule suffix, one of `r`, `w` or `rw`
+    pub fn parse(token: &str) -> Option<Self> {
+        use self::OpenType::*;
+        match token {
+            "r" => Some(Read),
+            "w" => Some(Write),
+            "rw" => Some(All),
+            _ => None,
+        }
+    }
+
+    /// Checks if a rule for this mode applies to a single `access`
+    pub fn covers(&self, access: &OpenType) -> bool {
+        *self == OpenType::All || self == access
+    }
+}
+
 impl From<u64> for OpenType {
     fn from(mode: u64) -> Self {
         Self::from(mode as i32)
@@ -48,21 +69,83 @@ impl From<i32> for OpenType {
 }
 
 /// Action to take for a given file
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Action {
-    Block(OpenType),
+    Allow,
+    /// Fail the `open` with the given error
+    Block(Errno),
     Replace(PathBuf),
+    /// Let the `open` succeed on `/dev/null` so reads are empty and writes vanish
+    Discard,
+    /// Serve generated contents instead of the file
+    Virtual(Content),
 }
 
 impl Action {
-    /// Checks if mode is allowed for action type
-    pub fn allows(&self, mode: &OpenType) -> bool {
+    /// Parse action from its rule suffix, `allow`, `block`, `discard` or an
+    /// errno name
+    pub fn parse(token: &str) -> Option<Self> {
+        match token {
+            "allow" => Some(Action::Allow),
+            "discard" => Some(Action::Discard),
+            "block" => Some(Action::Block(Errno::EPERM)),
+            _ => parse_errno(token).map(Action::Block),
+        }
+    }
+
+    /// Checks if action lets the `open` go through
+    pub fn allows(&self) -> bool {
         match self {
-            Action::Block(OpenType::All) => false,
-            Action::Block(typ) => *mode == OpenType::All || *typ != *mode,
-            Action::Replace(_) => true,
+            Action::Block(_) => false,
+            Action::Allow | Action::Replace(_) | Action::Discard | Action::Virtual(_) => true,
         }
     }
+
+    /// Describe what the action does to an `open` sent to `target` instead,
+    /// `None` if it goes through unchanged
+    pub fn effect(&self, target: Option<&Path>) -> Option<String> {
+        match (self, target) {
+            (Action::Block(errno), _) => Some(format!("BLOCK ({:?})", errno)),
+            (Action::Discard, _) => Some(String::from("DISCARD")),
+            (Action::Virtual(content), _) => Some(format!("REDIRECT => {}", content)),
+            (_, Some(new)) => Some(format!("REDIRECT => {}", new.display())),
+            (_, None) => None,
+        }
+    }
+}
+
+/// Errors a blocked `open` can be made to fail with
+static ERRNOS: &[Errno] = &[
+    Errno::EPERM,
+    Errno::ENOENT,
+    Errno::EIO,
+    Errno::ENXIO,
+    Errno::EAGAIN,
+    Errno::EACCES,
+    Errno::EBUSY,
+    Errno::EEXIST,
+    Errno::ENODEV,
+    Errno::ENOTDIR,
+    Errno::EISDIR,
+    Errno::EINVAL,
+    Errno::ENFILE,
+    Errno::EMFILE,
+    Errno::ETXTBSY,
+    Errno::EFBIG,
+    Errno::ENOSPC,
+    Errno::EROFS,
+    Errno::ENAMETOOLONG,
+    Errno::ELOOP,
+    Errno::EOPNOTSUPP,
+    Errno::EDQUOT,
+];
+
+/// Parse errno from its name, such as `ENOENT`
+pub fn parse_errno(name: &str) -> Option<Errno> {
+    ERRNOS
+        .iter()
+        .find(|errno| format!("{:?}", errno) == name)
+        .cloned()
 }
 
 #[cfg(test)]
@@ -84,4 +167,15 @@ mod test {
     fn extra() {
         assert_eq!(OpenType::from(O_RDONLY | O_CREAT | O_TRUNC), Read);
     }
+
+    /// Test action suffix parsing
+    #[test]
+    fn action() {
+        assert_eq!(Action::parse("allow"), Some(Action::Allow));
+        assert_eq!(Action::parse("block"), Some(Action::Block(Errno::EPERM)));
+        assert_eq!(Action::parse("ENOENT"), Some(Action::Block(Errno::ENOENT)));
+        assert_eq!(Action::parse("discard"), Some(Action::Discard));
+        assert_eq!(Action::parse("ENOTANERRNO"), None);
+        assert_eq!(Action::parse("r"), None);
+    }
 }
@@ -1,6 +1,8 @@
 mod util;
 use crate::util::*;
 
+use std::os::unix::fs::PermissionsExt;
+
 /// Test that no output fails
 #[test]
 fn no_args() {
@@ -96,3 +98,599 @@ fn redirect() {
         assert!(o.contains(TEST));
     });
 }
+
+/// Test that rules apply to forked children
+#[test]
+fn fork() {
+    with_tempfile(|f| {
+        let cmd = &format!("cat {} && true", f);
+        let o = output(&[f, "--", "sh", "-c", cmd]);
+        assert!(o.fail());
+        assert!(!o.contains(TEST));
+
+        let o = output(&["--", "sh", "-c", cmd]);
+        assert!(o.pass());
+        assert!(o.contains(TEST));
+    });
+}
+
+/// Test that relative paths are resolved in the child's working directory
+#[test]
+fn child_cwd() {
+    with_tempfile(|f| {
+        let path = std::path::Path::new(f);
+        let dir = path.parent().unwrap().to_str().unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let cmd = &format!("cd {} && cat {}", dir, name);
+
+        let o = output(&[f, "--", "sh", "-c", cmd]);
+        assert!(o.fail());
+        assert!(!o.contains(TEST));
+    });
+}
+
+/// Test that paths through `/proc/self` are resolved in the child
+#[test]
+fn proc_self() {
+    with_tempfile(|f| {
+        let path = std::path::Path::new(f);
+        let dir = path.parent().unwrap().to_str().unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        for link in &["/proc/self/cwd", "/proc/thread-self/cwd"] {
+            let cmd = &format!("cd {} && cat {}/{}", dir, link, name);
+            let o = output(&[f, "--", "sh", "-c", cmd]);
+            assert!(o.fail());
+            assert!(!o.contains(TEST));
+        }
+    });
+}
+
+/// Test that directory rules apply to their tree and allow rules override them
+#[test]
+fn tree() {
+    with_tempfile(|f| {
+        let dir = std::path::Path::new(f).parent().unwrap().to_str().unwrap();
+        let block = &format!("{}/", dir);
+        let o = output(&[block, "--", "cat", f]);
+        assert!(o.fail());
+        assert!(!o.contains(TEST));
+
+        let allow = &format!("{}:allow", f);
+        let o = output(&[block, allow, "--", "cat", f]);
+        assert!(o.pass());
+        assert!(o.contains(TEST));
+    });
+}
+
+/// Test that glob rules work and are shown in the log
+#[test]
+fn glob() {
+    with_tempfile(|f| {
+        let dir = std::path::Path::new(f).parent().unwrap().to_str().unwrap();
+        let glob = &format!("{}/*", dir);
+        let o = output(&["-l", glob, "--", "cat", f]);
+        assert!(o.fail());
+        assert!(!o.contains(TEST));
+        assert!(o.contains(&format!("BLOCKED (EPERM) by {}", glob)));
+    });
+}
+
+/// Test that rules are read from policy files
+#[test]
+fn policy_file() {
+    with_tempfile(|f| {
+        let policy = &format!("{}.policy", f);
+        let included = &format!("{}.included", f);
+        std::fs::write(policy, format!("# Test policy\ninclude {}\n", included)).unwrap();
+        std::fs::write(included, format!("{}:r  # no reading\n", f)).unwrap();
+
+        let o = output(&["-l", "-f", policy, "--", "cat", f]);
+        let _ = std::fs::remove_file(policy);
+        let _ = std::fs::remove_file(included);
+        assert!(o.fail());
+        assert!(!o.contains(TEST));
+        assert!(o.contains(&format!("({}:1)", included)));
+    });
+}
+
+/// Test that unmatched paths can be denied by default
+#[test]
+fn deny_by_default() {
+    with_tempfile(|f| {
+        let o = output(&["--deny-by-default=w", "--", "cat", f]);
+        assert!(o.pass());
+        assert!(o.contains(TEST));
+
+        let o = output(&["--deny-by-default=w", "--", "tee", f]);
+        assert!(o.fail());
+
+        let allow = &format!("{}:w:allow", f);
+        let o = output(&["--deny-by-default=w", allow, "--", "tee", f]);
+        assert!(o.pass());
+    });
+}
+
+/// Test that blocked opens fail with the chosen errno
+#[test]
+fn errno() {
+    with_tempfile(|f| {
+        let o = output(&[f, "--", "cat", f]);
+        assert!(o.fail());
+        assert!(o.contains("Operation not permitted"));
+
+        let block = &format!("{}:ENOENT", f);
+        let o = output(&["-l", block, "--", "cat", f]);
+        assert!(o.fail());
+        assert!(o.contains("No such file or directory"));
+        assert!(o.contains("BLOCKED (ENOENT)"));
+    });
+}
+
+/// Test that discarded writes succeed without touching the file
+#[test]
+fn discard() {
+    with_tempfile(|f| {
+        let discard = &format!("{}:w:discard", f);
+        let o = output(&[discard, "--", "tee", f]);
+        assert!(o.pass());
+        assert_eq!(std::fs::read_to_string(f).unwrap(), TEST);
+
+        let o = output(&[discard, "--", "cat", f]);
+        assert!(o.pass());
+        assert!(o.contains(TEST));
+    });
+}
+
+/// Test that redirects can apply to a single mode
+#[test]
+fn redirect_mode() {
+    with_tempfile(|f| {
+        let r = &format!("bar:r={}", f);
+        let o = output(&[r, "--", "cat", "bar"]);
+        assert!(o.pass());
+        assert!(o.contains(TEST));
+
+        let w = &format!("bar:w={}", f);
+        let o = output(&[w, "--", "cat", "bar"]);
+        assert!(o.fail());
+        assert!(!o.contains(TEST));
+    });
+}
+
+/// Test that directory redirects remap every path below them
+#[test]
+fn remap() {
+    with_tempfile(|f| {
+        let path = std::path::Path::new(f);
+        let dir = path.parent().unwrap().to_str().unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let remap = &format!("/noop-remap/={}/", dir);
+        let o = output(&["-l", remap, "--", "cat", &format!("/noop-remap/{}", name)]);
+        assert!(o.pass());
+        assert!(o.contains(TEST));
+        assert!(o.contains(&format!("=> {}", f)));
+    });
+}
+
+/// Test that redirect templates are filled in and their directories created
+#[test]
+fn template() {
+    with_tempfile(|f| {
+        let dir = format!("{}.d", f);
+        let r = &format!("/noop-template/={}/{{exe}}/{{relpath}}", dir);
+        let o = output(&[r, "--", "cp", f, "/noop-template/sub/copy"]);
+        let copy = std::fs::read_to_string(format!("{}/cp/sub/copy", dir));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(o.pass());
+        assert_eq!(copy.unwrap(), TEST);
+    });
+}
+
+/// Test that overlays take writes and serve them back to later runs
+#[test]
+fn overlay() {
+    with_tempfile(|f| {
+        let dir = &format!("{}.overlay", f);
+        let cmd = &format!("echo changed >> {} && cat {}", f, f);
+        let o = output(&["--overlay", dir, "--", "sh", "-c", cmd]);
+        let real = std::fs::read_to_string(f);
+        let again = output(&["--overlay", dir, "--", "cat", f]);
+        let _ = std::fs::remove_dir_all(dir);
+        assert!(o.pass());
+        assert!(o.contains(&format!("{}changed", TEST)));
+        assert_eq!(real.unwrap(), TEST);
+        assert!(again.contains("changed"));
+    });
+}
+
+/// Test that transactions stage changes until they are committed
+#[test]
+fn transaction() {
+    let data = std::env::temp_dir().join("noop-data");
+    let data = data.to_str().unwrap();
+    with_tempfile(|f| {
+        let new = &format!("{}.new", f);
+        let cmd = &format!("echo changed > {} && echo made > {} && cat {}", f, new, f);
+        let o = output_env(
+            &["--transaction=keep", "--", "sh", "-c", cmd],
+            "XDG_DATA_HOME",
+            data,
+        );
+        assert!(o.pass());
+        assert!(o.contains("changed"));
+        assert!(o.contains(&format!("modified {}", f)));
+        assert!(o.contains(&format!("created  {}", new)));
+        assert!(o.contains(&format!("-{}", TEST)));
+        assert_eq!(std::fs::read_to_string(f).unwrap(), TEST);
+        assert!(!std::path::Path::new(new).exists());
+
+        let id = o.err.split("noop commit ").nth(1).unwrap();
+        let id = id.split('`').next().unwrap();
+        let commit = output_env(&["commit", id], "XDG_DATA_HOME", data);
+        let committed = std::fs::read_to_string(new);
+        let _ = std::fs::remove_file(new);
+        assert!(commit.pass());
+        assert_eq!(std::fs::read_to_string(f).unwrap(), "changed\n");
+        assert_eq!(committed.unwrap(), "made\n");
+
+        let o = output_env(
+            &["--transaction=discard", "--", "rm", f],
+            "XDG_DATA_HOME",
+            data,
+        );
+        assert!(o.pass());
+        assert!(o.contains(&format!("deleted  {}", f)));
+        assert!(std::path::Path::new(f).exists());
+    });
+}
+
+/// Test that transactions stage the removal of real directories
+#[test]
+fn transaction_rmdir() {
+    let data = std::env::temp_dir().join("noop-data");
+    let data = data.to_str().unwrap();
+    with_tempfile(|f| {
+        let (dir, empty) = (&format!("{}.d", f), &format!("{}.e", f));
+        std::fs::create_dir_all(format!("{}/sub", dir)).unwrap();
+        std::fs::write(format!("{}/sub/file", dir), TEST).unwrap();
+        std::fs::create_dir_all(empty).unwrap();
+        let cmd = &format!("rm -rf {} && rmdir {}", dir, empty);
+        let o = output_env(
+            &["--transaction=discard", "--", "sh", "-c", cmd],
+            "XDG_DATA_HOME",
+            data,
+        );
+        let kept = (
+            std::fs::read_to_string(format!("{}/sub/file", dir)),
+            std::path::Path::new(empty).is_dir(),
+        );
+        let _ = std::fs::remove_dir_all(dir);
+        let _ = std::fs::remove_dir(empty);
+
+        assert!(o.pass());
+        assert!(o.contains(&format!("deleted  {}/sub\n", dir)));
+        assert!(o.contains(&format!("deleted  {}\n", empty)));
+        assert_eq!(kept.0.unwrap(), TEST);
+        assert!(kept.1);
+    });
+}
+
+/// Test that transactions stage modes and truncation and fail links
+#[test]
+fn transaction_metadata() {
+    let data = std::env::temp_dir().join("noop-data");
+    let data = data.to_str().unwrap();
+    with_tempfile(|f| {
+        let cmd = &format!(
+            "chmod 600 {0} && truncate -s 1 {0} && cat {0}; ln -s {0} {0}.s; ln {0} {0}.l",
+            f
+        );
+        let o = output_env(
+            &["--transaction=discard", "--", "sh", "-c", cmd],
+            "XDG_DATA_HOME",
+            data,
+        );
+        let links = (
+            std::fs::symlink_metadata(format!("{}.s", f)).is_ok(),
+            std::fs::symlink_metadata(format!("{}.l", f)).is_ok(),
+        );
+        let _ = std::fs::remove_file(format!("{}.s", f));
+        let _ = std::fs::remove_file(format!("{}.l", f));
+
+        assert!(o.fail());
+        assert!(o.out.starts_with(&TEST[..1]));
+        assert!(o.contains(&format!("modified {}", f)));
+        assert_eq!(std::fs::read_to_string(f).unwrap(), TEST);
+        let mode = std::fs::metadata(f).unwrap().permissions().mode();
+        assert_ne!(mode & 0o777, 0o600);
+        assert_eq!(links, (false, false));
+    });
+}
+
+/// Test that backed up writes go through and can be rolled back
+#[test]
+fn backup() {
+    let data = std::env::temp_dir().join("noop-data");
+    let data = data.to_str().unwrap();
+    with_tempfile(|f| {
+        let cmd = &format!("echo changed > {}", f);
+        let o = output_env(
+            &["-l", "--backup", "--", "sh", "-c", cmd],
+            "XDG_DATA_HOME",
+            data,
+        );
+        assert!(o.pass());
+        assert!(o.contains("BACKED UP"));
+        assert_eq!(std::fs::read_to_string(f).unwrap(), "changed\n");
+
+        let id = o.err.split("noop restore ").nth(1).unwrap();
+        let id = id.split('`').next().unwrap();
+        let restore = output_env(&["restore", id], "XDG_DATA_HOME", data);
+        assert!(restore.pass());
+        assert_eq!(std::fs::read_to_string(f).unwrap(), TEST);
+
+        // Blocked writes leave nothing to restore
+        let new = &format!("{}.new", f);
+        let cmd = &format!("echo changed > {}", new);
+        let block = &format!("{}:w", new);
+        let o = output_env(
+            &["--backup", block, "--", "sh", "-c", cmd],
+            "XDG_DATA_HOME",
+            data,
+        );
+        assert!(o.fail());
+        assert!(!o.contains("noop restore"));
+    });
+}
+
+/// Test serving generated contents instead of a file
+#[test]
+fn virtual_content() {
+    let o = output(&["/noop-virtual=@text:a=b\\n", "--", "cat", "/noop-virtual"]);
+    assert!(o.pass());
+    assert_eq!(o.out, "a=b\n");
+
+    let o = output(&[
+        "/noop-virtual=@cmd:echo generated",
+        "--",
+        "cat",
+        "/noop-virtual",
+    ]);
+    assert!(o.pass());
+    assert_eq!(o.out, "generated\n");
+
+    let o = output(&[
+        "/noop-virtual=@env:NOOP_UNSET_VARIABLE",
+        "--",
+        "cat",
+        "/noop-virtual",
+    ]);
+    assert!(o.fail());
+}
+
+/// Test transforming files before they are read
+#[test]
+fn transform() {
+    with_tempfile(|f| {
+        let gz = &format!("{}.gz", f);
+        let template = &format!("{}.in", f);
+        std::process::Command::new("gzip")
+            .arg("-k")
+            .arg(f)
+            .status()
+            .unwrap();
+        std::fs::write(template, "v=${NOOP_SUBST} $HOME").unwrap();
+        std::env::set_var("NOOP_SUBST", "x");
+
+        let gunzip = output(&[&format!("/noop-gz=@gunzip:{}", gz), "--", "cat", "/noop-gz"]);
+        let subst = output(&[
+            &format!("/noop-in=@subst:{}", template),
+            "--",
+            "cat",
+            "/noop-in",
+        ]);
+        let _ = std::fs::remove_file(gz);
+        let _ = std::fs::remove_file(template);
+        assert_eq!(gunzip.out, TEST);
+        assert_eq!(subst.out, "v=x $HOME");
+    });
+}
+
+/// Test that a recorded policy allows the recorded run and nothing else
+#[test]
+fn record() {
+    with_tempfile(|f| {
+        let policy = &format!("{}.policy", f);
+        let o = output(&["record", "-o", policy, "--", "cat", f]);
+        let recorded = std::fs::read_to_string(policy);
+        assert!(o.pass());
+        assert!(o.contains(TEST));
+
+        let allowed = output(&["-f", policy, "--", "cat", f]);
+        let blocked = output(&["-f", policy, "--", "cat", f, "/etc/hostname"]);
+        let _ = std::fs::remove_file(policy);
+        assert!(recorded.unwrap().contains(&format!("{}:r:allow", f)));
+        assert!(allowed.pass());
+        assert!(allowed.contains(TEST));
+        assert!(blocked.fail());
+    });
+}
+
+/// Test that a dry run reports blocks without enforcing them
+#[test]
+fn dry_run() {
+    with_tempfile(|f| {
+        let o = output(&["--dry-run", f, "--", "cat", f]);
+        assert!(o.pass());
+        assert!(o.contains(TEST));
+        assert!(o.contains("WOULD BLOCK (EPERM)"));
+        assert!(o.contains("1 opens would have been blocked or redirected"));
+        assert!(o.contains(&format!("{} (argument 2):", f)));
+
+        let o = output(&["--dry-run", &format!("{}=/dev/null", f), "--", "cat", f]);
+        assert!(o.contains(TEST));
+        assert!(o.contains("WOULD REDIRECT => /dev/null"));
+    });
+}
+
+/// Test replaying a saved trace through a policy without running again
+#[test]
+fn eval() {
+    with_tempfile(|f| {
+        let (trace, policy) = (&format!("{}.trace", f), &format!("{}.policy", f));
+        let o = output(&["--trace", trace, "--", "cat", f]);
+        assert!(o.pass());
+        std::fs::write(policy, format!("{}:r\n", f)).unwrap();
+
+        let o = output(&["eval", policy, trace]);
+        let _ = std::fs::remove_file(trace);
+        let _ = std::fs::remove_file(policy);
+        assert!(o.pass());
+        assert!(o.contains(&format!("{:?}, R) BLOCK (EPERM)", f)));
+        assert!(o.contains(", 1 blocked, 0 redirected"));
+    });
+}
+
+/// Test comparing the traces of two runs
+#[test]
+fn diff() {
+    with_tempfile(|f| {
+        let (old, new) = (&format!("{}.old", f), &format!("{}.new", f));
+        let cmd = &format!("cat {} > /dev/null", f);
+        let a = output(&["--trace", old, "--", "sh", "-c", cmd]);
+        let b = output(&[
+            "--trace",
+            new,
+            "--",
+            "sh",
+            "-c",
+            &format!("{}; echo >> {}", cmd, f),
+        ]);
+        let text = output(&["diff", old, new]);
+        let json = output(&["diff", "--json", old, new]);
+        let same = output(&["diff", old, old]);
+        let _ = std::fs::remove_file(old);
+        let _ = std::fs::remove_file(new);
+
+        assert!(a.pass() && b.pass());
+        assert!(text.pass());
+        assert!(text.contains(&format!("NEWLY WRITTEN:\n  {} (by ", f)));
+        assert!(json.contains("\"newly_written\":[{\"exes\":["));
+        assert!(same.contains("No differences"));
+    });
+}
+
+/// Test importing an strace log and making a policy from it
+#[test]
+fn import() {
+    with_tempfile(|f| {
+        let (log, trace, policy) = (
+            &format!("{}.log", f),
+            &format!("{}.trace", f),
+            &format!("{}.policy", f),
+        );
+        let lines = format!(
+            "42 execve(\"/bin/cat\", [\"cat\"], 0x1 /* 1 var */) = 0\n\
+             42 openat(AT_FDCWD, \"{}\", O_RDONLY|O_CLOEXEC) = 3\n\
+             42 creat(\"{}.out\", 0644) = -1 EACCES (Permission denied)\n",
+            f, f
+        );
+        std::fs::write(log, lines).unwrap();
+
+        let o = output(&["import", log]);
+        std::fs::write(trace, &o.out).unwrap();
+        let made = output(&["record", "-o", policy, "--from", trace]);
+        let recorded = std::fs::read_to_string(policy);
+        for file in &[log, trace, policy] {
+            let _ = std::fs::remove_file(file);
+        }
+
+        assert!(o.pass());
+        assert!(o.contains("\"exe\":\"cat\""));
+        assert!(o.contains("\"result\":-13"));
+        assert!(made.pass());
+        let recorded = recorded.unwrap();
+        assert!(recorded.contains(&format!("{}:r:allow", f)));
+        assert!(recorded.contains(&format!("{}.out:w:allow", f)));
+    });
+}
+
+/// Test explaining which rule decides an open
+#[test]
+fn explain() {
+    with_tempfile(|f| {
+        let policy = &format!("{}.policy", f);
+        std::fs::write(policy, format!("{}:w:ENOENT\n/tmp/\n", f)).unwrap();
+        let write = output(&["explain", "-f", policy, f, "--mode", "w"]);
+        let read = output(&["explain", "-f", policy, f]);
+        let _ = std::fs::remove_file(policy);
+
+        assert!(write.pass());
+        assert!(write.contains(&format!("* {}:w:ENOENT ({}:1)", f, policy)));
+        assert!(write.contains("action: BLOCK (ENOENT)"));
+        assert!(read.contains(&format!("* /tmp/ ({}:2)", policy)));
+    });
+}
+
+/// Test that linting fails on rules that never apply
+#[test]
+fn lint() {
+    with_tempfile(|f| {
+        let policy = &format!("{}.policy", f);
+        let dir = std::path::Path::new(f).parent().unwrap().display();
+        std::fs::write(policy, format!("{}/*.log:w\n{}/**:w:allow\n", dir, dir)).unwrap();
+        let dead = output(&["lint", policy]);
+        std::fs::write(policy, format!("{}:w\n{}:w:allow\n", f, f)).unwrap();
+        let twice = output(&["lint", policy]);
+        std::fs::write(policy, format!("{}:w\n/noop-missing/x\n", f)).unwrap();
+        let typo = output(&["lint", policy]);
+        std::fs::write(policy, format!("{}:w\n", f)).unwrap();
+        let clean = output(&["lint", policy]);
+        std::fs::write(policy, format!("{}=@nope:x\n", f)).unwrap();
+        let broken = output(&["lint", policy]);
+        let _ = std::fs::remove_file(policy);
+
+        assert!(dead.fail());
+        assert!(dead.contains(&format!(
+            "{}:1: error: never applies, {}/**:w:allow ({}:2)",
+            policy, dir, policy
+        )));
+        assert!(twice.pass());
+        assert!(twice.contains(&format!(
+            "{}:1: warning: {} is also set at {}:2",
+            policy, f, policy
+        )));
+        assert!(typo.fail());
+        assert!(typo.contains("/noop-missing/x does not exist"));
+        assert!(clean.pass());
+        assert!(clean.contains("0 errors, 0 warnings"));
+        assert!(broken.fail());
+        assert!(broken.err.starts_with(&format!("Error: {}:1: ", policy)));
+        assert!(!broken.contains("USAGE"));
+    });
+}
+
+/// Test that checking a policy fails on cases it decides otherwise
+#[test]
+fn test() {
+    with_tempfile(|f| {
+        let policy = &format!("{}.policy", f);
+        let cases = &format!("{}.cases", f);
+        std::fs::write(policy, format!("{}:w:ENOENT\n", f)).unwrap();
+        std::fs::write(cases, format!("w {} => ENOENT\nr {} => allow\n", f, f)).unwrap();
+        let pass = output(&["test", policy, cases]);
+        std::fs::write(cases, format!("# comment\nr {} => block\n", f)).unwrap();
+        let fail = output(&["test", policy, cases]);
+        let _ = std::fs::remove_file(policy);
+        let _ = std::fs::remove_file(cases);
+
+        assert!(pass.pass());
+        assert!(pass.contains("2 passed, 0 failed"));
+        assert!(fail.fail());
+        assert!(fail.contains(&format!(
+            "{}:2: r {} => block, got ALLOW by default",
+            cases, f
+        )));
+        assert!(fail.contains("0 passed, 1 failed"));
+    });
+}
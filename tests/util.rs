@@ -78,3 +78,15 @@ impl Output {
 pub fn output(args: &[&str]) -> Output {
     Output::new(process::Command::new(TARGET).args(args).output().unwrap())
 }
+
+/// Returns output wrapper for `TARGET` run with `args` and the environment
+/// variable `key` set to `value`
+pub fn output_env(args: &[&str], key: &str, value: &str) -> Output {
+    Output::new(
+        process::Command::new(TARGET)
+            .args(args)
+            .env(key, value)
+            .output()
+            .unwrap(),
+    )
+}